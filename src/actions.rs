@@ -5,13 +5,32 @@
 //  License:        BSD-2-Clause
 //  -------------------------------------------------------------
 
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use chrono::Utc;
+use log::warn;
+
 use crate::config::AlkaneConfig;
+use crate::db::history::{HistoryEntry, Trigger};
+use crate::db::lock::{LockError, SiteLock};
+use crate::db::logs::RunLog;
 use crate::db::Database;
 use crate::deploy::AlkaneDeployError;
-use crate::deploy::DeployError;
+use crate::deploy::{DeployError, DeploymentPlan, DeploymentResult};
+use crate::deploy::{ExecutionFailedError, LockHeldError};
+use crate::releases::Releases;
+use crate::removal::{check_site_path, remove_site_files, FilesRemoval, RemoveOptions};
+use crate::runner::sandbox::Sandbox;
+use crate::runner::site::{Revision, Site};
 use crate::runner::store::RecipesStore;
-use crate::runner::RecipeStatus;
+use crate::runner::user::RunAs;
+use crate::runner::{ExecutionError, OutputListener, RecipeOutput, RecipeStatus, RunOptions};
 use crate::server::kernel::run;
+use crate::services::site_name::is_valid_site_name;
 
 //  -------------------------------------------------------------
 //  Actions only available in CLI
@@ -28,68 +47,760 @@ pub async fn serve(config: AlkaneConfig) -> bool {
 fn run_deployment_action(
     site_name: &str,
     context: Option<String>,
+    revision: Option<Revision>,
+    trigger: Trigger,
+    listener: Option<OutputListener>,
     config: &AlkaneConfig,
     action: &str,
-) -> Result<RecipeStatus, DeployError> {
-    let db = Database::from_config(config).ok_or_else(|| {
-        let error = AlkaneDeployError::new("Can't initialize database", site_name, action);
-        DeployError::Alkane(error)
-    })?;
+) -> Result<DeploymentResult, DeployError> {
+    let fail = |kind: fn(AlkaneDeployError) -> DeployError, message: &str| {
+        kind(AlkaneDeployError::new(message, site_name, action))
+    };
 
-    let recipes = RecipesStore::from_config(config).ok_or_else(|| {
-        let error = AlkaneDeployError::new("Can't initialize recipes store", site_name, action);
-        DeployError::Alkane(error)
-    })?;
+    if !is_valid_site_name(site_name) {
+        return Err(fail(DeployError::InvalidRequest, "Invalid site name"));
+    }
 
-    let site = config.get_site(site_name, context).ok_or_else(|| {
-        let error = AlkaneDeployError::new("Can't resolve site path", site_name, action);
-        DeployError::Alkane(error)
-    })?;
+    let db = Database::from_config(config)
+        .ok_or_else(|| fail(DeployError::ConfigMissing, "Can't initialize database"))?;
+    let recipes = RecipesStore::from_config(config)
+        .ok_or_else(|| fail(DeployError::ConfigMissing, "Can't initialize recipes store"))?;
+    let mut site = config
+        .get_site(site_name, context)
+        .ok_or_else(|| fail(DeployError::SiteUnresolvable, "Can't resolve site path"))?;
+    site.revision = revision;
+
+    let user = get_run_as(site_name, &site.path, action, config)?;
+
+    let _lock = lock_site(&db, site_name, action, config)?;
+
+    // Deploy is resolved while holding the lock, so a concurrent
+    // deployment can't initialize the site in the meantime.
+    let action = resolve_action(&db, &site.name, action);
+
+    if !config.is_action_enabled(site_name, action) {
+        let message = format!("Action {} isn't enabled for this site", action);
+        return Err(fail(DeployError::ActionDisabled, &message));
+    }
+
+    check_recipe(&recipes, &site, action)?;
+
+    // With the releases layout, the recipe builds a new release directory,
+    // which becomes the live content only if the recipe succeeds.
+    // Custom actions, like clear-cache, run against the live content.
+    let releases = config
+        .uses_releases(&site.name)
+        .then(|| Releases::new(&site.path));
+    let mut release = match &releases {
+        None => None,
+        Some(releases) if !is_build_action(action) => {
+            site.path = releases.get_current_path().to_string_lossy().to_string();
 
-    let status = recipes.run_recipe(&site, action);
+            None
+        }
+        Some(releases) => {
+            let release = releases.create().map_err(|error| {
+                let message = format!("Can't create release directory: {}", error);
+                fail(DeployError::Internal, &message)
+            })?;
+
+            site.path = releases
+                .get_release_path(&release)
+                .to_string_lossy()
+                .to_string();
+
+            Some(release)
+        }
+    };
+
+    let run_id = db.allocate_run_id(&site.name);
+
+    let started_at = Utc::now();
+    let start = Instant::now();
+    let options = RunOptions {
+        timeout: config.get_recipe_timeout(&site.name),
+        user,
+        sandbox: get_sandbox(&site, config),
+        listener,
+    };
+    let output = recipes.run_recipe(&site, action, &options);
+    let status = get_run_status(&output);
 
     if action == "init" && status == RecipeStatus::Success {
         db.set_initialized(&site.name);
     }
 
-    Ok(status)
+    if let (Some(releases), Some(name)) = (&releases, &release) {
+        if !publish_release(releases, name, &status, config) {
+            release = None;
+        }
+    }
+
+    if let (Some(run_id), Some(output)) = (run_id, get_recipe_output(&output)) {
+        let log = RunLog {
+            run_id,
+            action: action.to_string(),
+            stdout: output.stdout.clone(),
+            stderr: output.stderr.clone(),
+        };
+        db.write_run_log(&site.name, &log, config.get_logs_retention());
+    }
+
+    let mut entry = HistoryEntry::new(
+        run_id,
+        started_at,
+        action,
+        status.clone(),
+        start.elapsed(),
+        site.context.as_deref(),
+        trigger,
+    );
+    let usage = get_recipe_output(&output).and_then(|output| output.usage);
+    entry.release = release.clone();
+    entry.usage = usage;
+    db.append_history(&site.name, &entry);
+
+    let mut result = DeploymentResult::new(&site.name, action, status, started_at);
+    result.run_id = run_id;
+    result.release = release;
+    result.exit_code = get_recipe_output(&output).and_then(|output| output.exit_code);
+    result.signal = get_signal(&output);
+    result.usage = usage;
+
+    to_deployment_outcome(output.err(), result)
+}
+
+/// Resolves deploy as init or update, according if the site is initialized
+fn resolve_action<'a>(db: &Database, site_name: &str, action: &'a str) -> &'a str {
+    match action {
+        "deploy" if db.is_initialized(site_name) => "update",
+        "deploy" => "init",
+        _ => action,
+    }
+}
+
+/// Determines if the action builds the site content, as init and update
+fn is_build_action(action: &str) -> bool {
+    action == "init" || action == "update"
+}
+
+/// Determines if an action name is safe to use as recipe file name:
+/// only letters, digits, dashes and underscores are allowed,
+/// so a name like ../../bin/sh can't escape the recipes directory.
+pub fn is_valid_action_name(action: &str) -> bool {
+    !action.is_empty()
+        && action.len() <= 64
+        && !action.starts_with('-')
+        && action
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// The actions with their own entry point, and their own checks,
+/// like the confirmation required to remove a site
+const RESERVED_ACTIONS: [&str; 5] = ["init", "update", "deploy", "rollback", "remove"];
+
+/// Determines if an action can't be run as a custom action
+pub fn is_reserved_action_name(action: &str) -> bool {
+    RESERVED_ACTIONS.contains(&action)
+}
+
+/// Ensures the recipe exists and can be executed before running it
+fn check_recipe(recipes: &RecipesStore, site: &Site, action: &str) -> Result<(), DeployError> {
+    let path = recipes.get_recipe_path(site, action);
+    let fail = |kind: fn(AlkaneDeployError) -> DeployError, message: String| {
+        kind(AlkaneDeployError::new(message.as_str(), &site.name, action))
+    };
+
+    let metadata = fs::metadata(&path)
+        .ok()
+        .filter(|metadata| metadata.is_file())
+        .ok_or_else(|| {
+            fail(
+                DeployError::RecipeNotFound,
+                format!("No recipe at {}", path),
+            )
+        })?;
+
+    if metadata.permissions().mode() & 0o111 == 0 {
+        let message = format!("Recipe {} isn't executable", path);
+        return Err(fail(DeployError::RecipeNotExecutable, message));
+    }
+
+    Ok(())
+}
+
+/// Gets the output of a recipe run, if the recipe could be spawned
+fn get_recipe_output(output: &Result<RecipeOutput, ExecutionError>) -> Option<&RecipeOutput> {
+    match output {
+        Ok(output) => Some(output),
+        Err(error) => error.get_output(),
+    }
+}
+
+/// Gets the signal which terminated the recipe, if any
+fn get_signal(output: &Result<RecipeOutput, ExecutionError>) -> Option<i32> {
+    match output {
+        Err(ExecutionError::Signal { signal, .. }) => Some(*signal),
+        _ => None,
+    }
+}
+
+/// Gets the status to journal for a recipe run: a recipe which
+/// can't be spawned is journaled as an error.
+fn get_run_status(output: &Result<RecipeOutput, ExecutionError>) -> RecipeStatus {
+    match get_recipe_output(output) {
+        Some(output) => output.status.clone(),
+        None => RecipeStatus::Error,
+    }
+}
+
+/// Reports a recipe which couldn't run to completion or has timed out
+/// as an error, once the run has been journaled.
+fn to_deployment_outcome(
+    execution_error: Option<ExecutionError>,
+    result: DeploymentResult,
+) -> Result<DeploymentResult, DeployError> {
+    if let Some(error) = execution_error {
+        return Err(DeployError::Execution(ExecutionFailedError {
+            site_name: result.site_name.clone(),
+            action: result.action.clone(),
+            error,
+            result: Box::new(result),
+        }));
+    }
+
+    if result.status == RecipeStatus::Timeout {
+        let message = match result.run_id {
+            Some(run_id) => format!("Recipe timed out, output is available for run {}", run_id),
+            None => "Recipe timed out".to_string(),
+        };
+        let error = AlkaneDeployError::new(
+            message.as_str(),
+            result.site_name.as_str(),
+            result.action.as_str(),
+        );
+        return Err(DeployError::Timeout(error, Box::new(result)));
+    }
+
+    Ok(result)
+}
+
+/// Resolves the user to run the site recipes as, if configured
+fn get_run_as(
+    site_name: &str,
+    site_path: &str,
+    action: &str,
+    config: &AlkaneConfig,
+) -> Result<Option<RunAs>, DeployError> {
+    match config.get_site_user(site_name) {
+        None => Ok(None),
+        Some(user) => RunAs::resolve(&user, site_path)
+            .map(Some)
+            .map_err(|message| {
+                DeployError::UserUnresolvable(AlkaneDeployError::new(
+                    message.as_str(),
+                    site_name,
+                    action,
+                ))
+            }),
+    }
+}
+
+/// Gets the sandbox to run the site recipes in, if enabled,
+/// with the site directory as working directory.
+fn get_sandbox(site: &Site, config: &AlkaneConfig) -> Option<Sandbox> {
+    config
+        .get_site_sandbox(&site.name)
+        .map(|sandbox| sandbox.with_site_path(&site.path))
+}
+
+fn lock_site(
+    db: &Database,
+    site_name: &str,
+    action: &str,
+    config: &AlkaneConfig,
+) -> Result<SiteLock, DeployError> {
+    db.lock(site_name, action, config.get_lock_timeout())
+        .map_err(|error| match error {
+            LockError::Held(holder) => DeployError::LockHeld(LockHeldError {
+                site_name: site_name.to_string(),
+                action: action.to_string(),
+                holder,
+            }),
+            LockError::IO(error) => {
+                let message = format!("Can't acquire deployment lock: {}", error);
+                DeployError::Internal(AlkaneDeployError::new(message.as_str(), site_name, action))
+            }
+        })
+}
+
+/// Makes the release live if the recipe succeeded, otherwise discards it.
+/// Returns true if the release is live.
+fn publish_release(
+    releases: &Releases,
+    release: &str,
+    status: &RecipeStatus,
+    config: &AlkaneConfig,
+) -> bool {
+    match status {
+        RecipeStatus::Success | RecipeStatus::Warning => match releases.activate(release) {
+            Ok(_) => {
+                releases.prune(config.get_releases_retention());
+
+                true
+            }
+            Err(error) => {
+                warn!("Can't activate release {}: {:?}", release, error);
+
+                false
+            }
+        },
+
+        _ => {
+            if let Err(error) = releases.remove(release) {
+                warn!("Can't remove failed release {}: {:?}", release, error);
+            }
+
+            false
+        }
+    }
 }
 
 pub fn initialize(
     site_name: &str,
     context: Option<String>,
+    trigger: Trigger,
+    listener: Option<OutputListener>,
     config: &AlkaneConfig,
-) -> Result<RecipeStatus, DeployError> {
-    run_deployment_action(site_name, context, config, "init")
+) -> Result<DeploymentResult, DeployError> {
+    run_deployment_action(site_name, context, None, trigger, listener, config, "init")
 }
 
 pub fn update(
     site_name: &str,
     context: Option<String>,
+    trigger: Trigger,
+    listener: Option<OutputListener>,
     config: &AlkaneConfig,
-) -> Result<RecipeStatus, DeployError> {
-    run_deployment_action(site_name, context, config, "update")
+) -> Result<DeploymentResult, DeployError> {
+    run_deployment_action(
+        site_name,
+        context,
+        None,
+        trigger,
+        listener,
+        config,
+        "update",
+    )
 }
 
 pub fn deploy(
     site_name: &str,
     context: Option<String>,
+    trigger: Trigger,
+    listener: Option<OutputListener>,
+    config: &AlkaneConfig,
+) -> Result<DeploymentResult, DeployError> {
+    run_deployment_action(
+        site_name,
+        context,
+        None,
+        trigger,
+        listener,
+        config,
+        "deploy",
+    )
+}
+
+/// Deploys a revision pushed to a Git repository,
+/// exposed to the recipe as ALKANE_REPOSITORY, ALKANE_BRANCH and ALKANE_COMMIT_SHA.
+pub fn deploy_revision(
+    site_name: &str,
+    context: Option<String>,
+    revision: Revision,
+    trigger: Trigger,
+    listener: Option<OutputListener>,
+    config: &AlkaneConfig,
+) -> Result<DeploymentResult, DeployError> {
+    run_deployment_action(
+        site_name,
+        context,
+        Some(revision),
+        trigger,
+        listener,
+        config,
+        "deploy",
+    )
+}
+
+/// Describes what a deployment action would do, without running anything
+pub fn plan(
+    site_name: &str,
+    context: Option<String>,
+    action: &str,
+    config: &AlkaneConfig,
+) -> Result<DeploymentPlan, DeployError> {
+    let fail = |kind: fn(AlkaneDeployError) -> DeployError, message: &str| {
+        kind(AlkaneDeployError::new(message, site_name, action))
+    };
+
+    if !is_valid_site_name(site_name) {
+        return Err(fail(DeployError::InvalidRequest, "Invalid site name"));
+    }
+
+    if !is_valid_action_name(action) {
+        return Err(fail(DeployError::InvalidRequest, "Invalid action name"));
+    }
+
+    let db = Database::from_config(config)
+        .ok_or_else(|| fail(DeployError::ConfigMissing, "Can't initialize database"))?;
+    let recipes = RecipesStore::from_config(config)
+        .ok_or_else(|| fail(DeployError::ConfigMissing, "Can't initialize recipes store"))?;
+    let mut site = config
+        .get_site(site_name, context)
+        .ok_or_else(|| fail(DeployError::SiteUnresolvable, "Can't resolve site path"))?;
+    let site_subdir = config
+        .get_site_subdir(site_name)
+        .ok_or_else(|| fail(DeployError::SiteUnresolvable, "Can't resolve site path"))?;
+
+    let resolved_action = resolve_action(&db, site_name, action);
+
+    let uses_releases = config.uses_releases(site_name);
+    let new_release = uses_releases && is_build_action(resolved_action);
+    if uses_releases {
+        let releases = Releases::new(&site.path);
+
+        let path = if new_release {
+            releases.get_release_path("<new release>")
+        } else {
+            releases.get_current_path()
+        };
+        site.path = path.to_string_lossy().to_string();
+    }
+
+    let recipe_path = recipes.get_recipe_path(&site, resolved_action);
+    let recipe_metadata = fs::metadata(&recipe_path).ok();
+
+    Ok(DeploymentPlan {
+        config_file: config.get_path().map(String::from),
+        site_name: site_name.to_string(),
+        site_directory_template: config.get_site_directory_template().to_string(),
+        site_subdir,
+        site_path: site.path.clone(),
+        action: action.to_string(),
+        resolved_action: resolved_action.to_string(),
+        action_enabled: config.is_action_enabled(site_name, resolved_action),
+        recipe_exists: recipe_metadata
+            .as_ref()
+            .map(|metadata| metadata.is_file())
+            .unwrap_or(false),
+        recipe_executable: recipe_metadata
+            .as_ref()
+            .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+            .unwrap_or(false),
+        recipe_path,
+        new_release,
+        timeout: config
+            .get_recipe_timeout(site_name)
+            .map(|timeout| timeout.as_secs()),
+        user: config.get_site_user(site_name).map(|user| user.to_string()),
+        environment: recipes.get_environment(&site).into_iter().collect(),
+    })
+}
+
+/// Runs a custom recipe for the site, like clear-cache or reindex
+pub fn run_custom_action(
+    site_name: &str,
+    action: &str,
+    context: Option<String>,
+    trigger: Trigger,
+    listener: Option<OutputListener>,
     config: &AlkaneConfig,
-) -> Result<RecipeStatus, DeployError> {
-    if is_present(site_name, config) {
-        run_deployment_action(site_name, context, config, "update")
-    } else {
-        run_deployment_action(site_name, context, config, "init")
+) -> Result<DeploymentResult, DeployError> {
+    if !is_valid_action_name(action) {
+        let error = AlkaneDeployError::new("Invalid action name", site_name, action);
+        return Err(DeployError::InvalidRequest(error));
+    }
+
+    if is_reserved_action_name(action) {
+        let message = format!("Action {} can't be run as a custom action", action);
+        let error = AlkaneDeployError::new(message.as_str(), site_name, action);
+        return Err(DeployError::InvalidRequest(error));
+    }
+
+    run_deployment_action(site_name, context, None, trigger, listener, config, action)
+}
+
+/// Decommissions a site: runs the remove recipe if any, deletes or archives
+/// the site directory, then clears the site state in the database.
+pub fn remove(
+    site_name: &str,
+    options: RemoveOptions,
+    trigger: Trigger,
+    config: &AlkaneConfig,
+) -> Result<DeploymentResult, DeployError> {
+    let action = "remove";
+    let fail = |kind: fn(AlkaneDeployError) -> DeployError, message: &str| {
+        kind(AlkaneDeployError::new(message, site_name, action))
+    };
+
+    if !is_valid_site_name(site_name) {
+        return Err(fail(DeployError::InvalidRequest, "Invalid site name"));
+    }
+
+    if !options.confirm {
+        return Err(fail(
+            DeployError::InvalidRequest,
+            "Site removal must be confirmed",
+        ));
+    }
+
+    if !config.is_action_enabled(site_name, action) {
+        let message = "Action remove isn't enabled for this site";
+        return Err(fail(DeployError::ActionDisabled, message));
     }
+
+    let db = Database::from_config(config)
+        .ok_or_else(|| fail(DeployError::ConfigMissing, "Can't initialize database"))?;
+    let recipes = RecipesStore::from_config(config)
+        .ok_or_else(|| fail(DeployError::ConfigMissing, "Can't initialize recipes store"))?;
+    let mut site = config
+        .get_site(site_name, None)
+        .ok_or_else(|| fail(DeployError::SiteUnresolvable, "Can't resolve site path"))?;
+    let archives_root = config
+        .get_archives_root()
+        .ok_or_else(|| fail(DeployError::ConfigMissing, "Can't resolve archives root"))?;
+
+    // The site directory is checked before the remove recipe runs,
+    // so a site which can't be removed is left untouched.
+    let site_path = match options.files {
+        FilesRemoval::Keep => PathBuf::from(&site.path),
+
+        _ if config.is_site_path_shared(site_name) => {
+            let message =
+                "Site directory is shared with the sites matching the same pattern, files must be kept";
+            return Err(fail(DeployError::InvalidRequest, message));
+        }
+
+        _ => {
+            let sites_root = config
+                .get_root("sites")
+                .ok_or_else(|| fail(DeployError::ConfigMissing, "Can't resolve sites root"))?;
+            let protected_roots: Vec<_> = ["db", "recipes"]
+                .iter()
+                .filter_map(|root| config.get_root(root))
+                .chain([archives_root.clone()])
+                .collect();
+
+            check_site_path(Path::new(&site.path), &sites_root, &protected_roots).map_err(
+                |error| {
+                    let message = format!("Site directory can't be removed: {}", error);
+                    fail(DeployError::InvalidRequest, &message)
+                },
+            )?
+        }
+    };
+
+    let _lock = lock_site(&db, site_name, action, config)?;
+
+    let started_at = Utc::now();
+    let start = Instant::now();
+
+    // The remove recipe can for example drop the site database.
+    // If it fails, the site is left untouched.
+    let mut run_id = None;
+    let mut status = RecipeStatus::Success;
+    let mut exit_code = None;
+    let mut signal = None;
+    let mut usage = None;
+    let mut execution_error = None;
+    if recipes.has_recipe(&site, action) {
+        check_recipe(&recipes, &site, action)?;
+        let user = get_run_as(site_name, &site.path, action, config)?;
+
+        if config.uses_releases(site_name) {
+            site.path = Releases::new(&site.path)
+                .get_current_path()
+                .to_string_lossy()
+                .to_string();
+        }
+
+        let options = RunOptions {
+            timeout: config.get_recipe_timeout(site_name),
+            user,
+            sandbox: get_sandbox(&site, config),
+            listener: None,
+        };
+        run_id = db.allocate_run_id(site_name);
+        let output = recipes.run_recipe(&site, action, &options);
+        status = get_run_status(&output);
+
+        if let (Some(run_id), Some(output)) = (run_id, get_recipe_output(&output)) {
+            let log = RunLog {
+                run_id,
+                action: action.to_string(),
+                stdout: output.stdout.clone(),
+                stderr: output.stderr.clone(),
+            };
+            db.write_run_log(site_name, &log, config.get_logs_retention());
+        }
+
+        exit_code = get_recipe_output(&output).and_then(|output| output.exit_code);
+        signal = get_signal(&output);
+        usage = get_recipe_output(&output).and_then(|output| output.usage);
+        execution_error = output.err();
+    }
+
+    if matches!(status, RecipeStatus::Success | RecipeStatus::Warning) {
+        remove_site_files(site_name, &site_path, options.files, &archives_root).map_err(
+            |error| {
+                let message = format!("Can't remove site directory: {}", error);
+                fail(DeployError::Internal, &message)
+            },
+        )?;
+
+        db.clear_site(site_name).map_err(|error| {
+            let message = format!("Can't clear site state: {}", error);
+            fail(DeployError::Internal, &message)
+        })?;
+    }
+
+    let mut entry = HistoryEntry::new(
+        run_id,
+        started_at,
+        action,
+        status.clone(),
+        start.elapsed(),
+        None,
+        trigger,
+    );
+    entry.usage = usage;
+    db.append_history(site_name, &entry);
+
+    let mut result = DeploymentResult::new(site_name, action, status, started_at);
+    result.run_id = run_id;
+    result.exit_code = exit_code;
+    result.signal = signal;
+    result.usage = usage;
+
+    to_deployment_outcome(execution_error, result)
+}
+
+/// Points the live content of a site using the releases layout
+/// to the specified release, or by default to the previous one.
+pub fn rollback(
+    site_name: &str,
+    to: Option<String>,
+    trigger: Trigger,
+    config: &AlkaneConfig,
+) -> Result<DeploymentResult, DeployError> {
+    let action = "rollback";
+    let fail = |kind: fn(AlkaneDeployError) -> DeployError, message: &str| {
+        kind(AlkaneDeployError::new(message, site_name, action))
+    };
+
+    if !is_valid_site_name(site_name) {
+        return Err(fail(DeployError::InvalidRequest, "Invalid site name"));
+    }
+
+    if !config.uses_releases(site_name) {
+        let message = "Site doesn't use the releases layout";
+        return Err(fail(DeployError::InvalidRequest, message));
+    }
+
+    if !config.is_action_enabled(site_name, action) {
+        let message = "Action rollback isn't enabled for this site";
+        return Err(fail(DeployError::ActionDisabled, message));
+    }
+
+    let db = Database::from_config(config)
+        .ok_or_else(|| fail(DeployError::ConfigMissing, "Can't initialize database"))?;
+    let path = config
+        .get_site_path(site_name)
+        .ok_or_else(|| fail(DeployError::SiteUnresolvable, "Can't resolve site path"))?;
+
+    let _lock = lock_site(&db, site_name, action, config)?;
+
+    let started_at = Utc::now();
+    let start = Instant::now();
+
+    let releases = Releases::new(path);
+    let release = match to {
+        Some(release) => release,
+        None => releases.get_previous().ok_or_else(|| {
+            fail(
+                DeployError::InvalidRequest,
+                "No previous release to roll back to",
+            )
+        })?,
+    };
+
+    releases.activate(&release).map_err(|error| {
+        let message = format!("Can't activate release {}: {}", release, error);
+
+        match error.kind() {
+            io::ErrorKind::NotFound => fail(DeployError::InvalidRequest, &message),
+            _ => fail(DeployError::Internal, &message),
+        }
+    })?;
+
+    let mut entry = HistoryEntry::new(
+        None,
+        started_at,
+        action,
+        RecipeStatus::Success,
+        start.elapsed(),
+        None,
+        trigger,
+    );
+    entry.release = Some(release.clone());
+    db.append_history(site_name, &entry);
+
+    let mut result = DeploymentResult::new(site_name, action, RecipeStatus::Success, started_at);
+    result.release = Some(release);
+
+    Ok(result)
 }
 
 pub fn is_present(site_name: &str, config: &AlkaneConfig) -> bool {
-    match Database::from_config(&config) {
+    if !is_valid_site_name(site_name) {
+        return false;
+    }
+
+    match Database::from_config(config) {
         None => false,
         Some(db) => db.is_initialized(site_name),
     }
 }
 
+pub fn get_history(site_name: &str, config: &AlkaneConfig) -> Vec<HistoryEntry> {
+    if !is_valid_site_name(site_name) {
+        return Vec::new();
+    }
+
+    match Database::from_config(config) {
+        None => Vec::new(),
+        Some(db) => db.get_history(site_name),
+    }
+}
+
+/// Gets the output of a run, or of the last run available if run_id is None
+pub fn get_run_log(site_name: &str, run_id: Option<u64>, config: &AlkaneConfig) -> Option<RunLog> {
+    if !is_valid_site_name(site_name) {
+        return None;
+    }
+
+    let db = Database::from_config(config)?;
+
+    let run_id = match run_id {
+        Some(run_id) => run_id,
+        None => *db.get_run_ids(site_name).last()?,
+    };
+
+    db.get_run_log(site_name, run_id)
+}
+
 //  -------------------------------------------------------------
 //  Tests
 //  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
@@ -102,7 +813,120 @@ mod tests {
     pub fn test_is_present() {
         let config = AlkaneConfig::load().unwrap();
 
-        assert_eq!(true, is_present("foo.acme.tld", &config));
-        assert_eq!(false, is_present("notexisting.acme.tld", &config));
+        assert!(is_present("foo.acme.tld", &config));
+        assert!(!is_present("notexisting.acme.tld", &config));
+    }
+
+    #[test]
+    pub fn test_is_valid_action_name() {
+        assert!(is_valid_action_name("update"));
+        assert!(is_valid_action_name("clear-cache"));
+        assert!(is_valid_action_name("maintenance_on"));
+
+        assert!(!is_valid_action_name(""));
+        assert!(!is_valid_action_name(".."));
+        assert!(!is_valid_action_name("../../bin/sh"));
+        assert!(!is_valid_action_name("foo/bar"));
+        assert!(!is_valid_action_name("-rf"));
+        assert!(!is_valid_action_name("clear cache"));
+    }
+
+    #[test]
+    pub fn test_run_reserved_action() {
+        let config = AlkaneConfig::load().unwrap();
+
+        for action in RESERVED_ACTIONS {
+            let result =
+                run_custom_action("foo.acme.tld", action, None, Trigger::Cli, None, &config);
+
+            assert!(
+                matches!(result, Err(DeployError::InvalidRequest(_))),
+                "Action {} shouldn't run as a custom action",
+                action
+            );
+        }
+    }
+
+    #[test]
+    pub fn test_plan() {
+        let config = AlkaneConfig::load().unwrap();
+
+        let deployment_plan = plan(
+            "foo.acme.tld",
+            Some("CH3-CH3".to_string()),
+            "deploy",
+            &config,
+        )
+        .unwrap();
+        assert_eq!(Some(".alkane.conf"), deployment_plan.config_file.as_deref());
+        assert_eq!("update", deployment_plan.resolved_action);
+        assert!(deployment_plan.recipe_exists);
+        assert!(deployment_plan.recipe_executable);
+        assert_eq!(Some(600), deployment_plan.timeout);
+        assert_eq!(
+            "CH3-CH3",
+            deployment_plan.environment["ALKANE_SITE_CONTEXT"]
+        );
+
+        let deployment_plan = plan("bar.acme.tld", None, "deploy", &config).unwrap();
+        assert_eq!("init", deployment_plan.resolved_action);
+        assert!(!deployment_plan.recipe_exists);
+
+        assert!(plan("foo.acme.tld", None, "../update", &config).is_err());
+    }
+
+    #[test]
+    pub fn test_check_recipe() {
+        let config = AlkaneConfig::load().unwrap();
+        let recipes = RecipesStore::from_config(&config).unwrap();
+        let site = config.get_site("foo.acme.tld", None).unwrap();
+
+        assert!(check_recipe(&recipes, &site, "update").is_ok());
+
+        let error = check_recipe(&recipes, &site, "notexisting").unwrap_err();
+        assert_eq!("recipe_not_found", error.get_code());
+    }
+
+    #[test]
+    pub fn test_deploy_error_codes() {
+        let config = AlkaneConfig::load().unwrap();
+
+        let error = run_custom_action(
+            "foo.acme.tld",
+            "../update",
+            None,
+            Trigger::Cli,
+            None,
+            &config,
+        )
+        .unwrap_err();
+        assert_eq!("invalid_request", error.get_code());
+
+        let error = plan("tld", None, "update", &config).unwrap_err();
+        assert_eq!("site_unresolvable", error.get_code());
+
+        let options = RemoveOptions::default();
+        let error = remove("foo.acme.tld", options, Trigger::Cli, &config).unwrap_err();
+        assert_eq!("invalid_request", error.get_code());
+
+        let error = update("..", None, Trigger::Cli, None, &config).unwrap_err();
+        assert_eq!("invalid_request", error.get_code());
+
+        let error = plan("..acme.tld", None, "update", &config).unwrap_err();
+        assert_eq!("invalid_request", error.get_code());
+    }
+
+    #[test]
+    pub fn test_timeout_keeps_result() {
+        let mut result =
+            DeploymentResult::new("foo.acme.tld", "update", RecipeStatus::Timeout, Utc::now());
+        result.run_id = Some(42);
+
+        let error = to_deployment_outcome(None, result).unwrap_err();
+        assert_eq!("timeout", error.get_code());
+        assert_eq!(
+            Some(42),
+            error.get_result().and_then(|result| result.run_id)
+        );
     }
 }
@@ -0,0 +1,507 @@
+//  -------------------------------------------------------------
+//  Alkane :: Check
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+//  Project:        Nasqueron
+//  License:        BSD-2-Clause
+//  Description:    Pre-flight validation of configuration and recipes
+//  -------------------------------------------------------------
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::Path;
+
+use glob::Pattern;
+use serde::Serialize;
+
+use crate::actions::is_valid_action_name;
+use crate::config::{AlkaneConfig, SiteUser};
+use crate::runner::store::RecipesStore;
+use crate::runner::user::RunAs;
+use crate::services::site_name::is_valid_site_name;
+use crate::services::tld::extract_domain_parts;
+
+/// The recipes every site should have
+const REQUIRED_RECIPES: [&str; 2] = ["init", "update"];
+
+//  -------------------------------------------------------------
+//  Check report
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, Serialize)]
+pub enum CheckLevel {
+    Ok,
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CheckResult {
+    /// What has been checked, like "recipe update"
+    pub check: String,
+
+    pub level: CheckLevel,
+
+    pub message: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct CheckReport {
+    /// Checks of the configuration itself
+    pub config: Vec<CheckResult>,
+
+    /// Checks for each site, by site name
+    pub sites: BTreeMap<String, Vec<CheckResult>>,
+}
+
+impl CheckReport {
+    /// Gets the most severe level of the report
+    pub fn get_level(&self) -> CheckLevel {
+        self.config
+            .iter()
+            .chain(self.sites.values().flatten())
+            .map(|result| result.level)
+            .fold(CheckLevel::Ok, |level, result_level| {
+                if result_level > level {
+                    result_level
+                } else {
+                    level
+                }
+            })
+    }
+
+    /// Gets the exit code, inspired by the Nagios ones like the recipes
+    pub fn to_status_code(&self) -> i32 {
+        match self.get_level() {
+            CheckLevel::Ok => 0,
+            CheckLevel::Warning => 1,
+            CheckLevel::Error => 2,
+        }
+    }
+}
+
+impl Display for CheckReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Configuration")?;
+        write_results(f, &self.config)?;
+
+        for (site_name, results) in &self.sites {
+            writeln!(f)?;
+            writeln!(f, "Site {}", site_name)?;
+            write_results(f, results)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn write_results(f: &mut Formatter<'_>, results: &[CheckResult]) -> std::fmt::Result {
+    for result in results {
+        let level = match result.level {
+            CheckLevel::Ok => "OK",
+            CheckLevel::Warning => "WARNING",
+            CheckLevel::Error => "ERROR",
+        };
+
+        writeln!(f, "  [{:7}] {}: {}", level, result.check, result.message)?;
+    }
+
+    Ok(())
+}
+
+/// Collects check results for a section of the report
+#[derive(Default)]
+struct Checks(Vec<CheckResult>);
+
+impl Checks {
+    fn add<S: AsRef<str>>(&mut self, check: &str, level: CheckLevel, message: S) {
+        self.0.push(CheckResult {
+            check: check.to_string(),
+            level,
+            message: message.as_ref().to_string(),
+        });
+    }
+
+    fn ok<S: AsRef<str>>(&mut self, check: &str, message: S) {
+        self.add(check, CheckLevel::Ok, message);
+    }
+
+    fn warning<S: AsRef<str>>(&mut self, check: &str, message: S) {
+        self.add(check, CheckLevel::Warning, message);
+    }
+
+    fn error<S: AsRef<str>>(&mut self, check: &str, message: S) {
+        self.add(check, CheckLevel::Error, message);
+    }
+}
+
+//  -------------------------------------------------------------
+//  Checks
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+/// Checks the configuration, then each of the specified sites
+pub fn check(config: &AlkaneConfig, site_names: &[String]) -> CheckReport {
+    CheckReport {
+        config: check_config(config),
+        sites: site_names
+            .iter()
+            .map(|site_name| (site_name.clone(), check_site(config, site_name)))
+            .collect(),
+    }
+}
+
+fn check_config(config: &AlkaneConfig) -> Vec<CheckResult> {
+    let mut checks = Checks::default();
+
+    match config.get_path() {
+        Some(path) => checks.ok("config", format!("loaded from {}", path)),
+        None => checks.ok("config", "loaded"),
+    }
+
+    for (root, is_required) in [("sites", true), ("recipes", true), ("db", false)] {
+        let check = format!("root {}", root);
+
+        match config.get_root(root) {
+            Some(path) if Path::new(&path).is_dir() => checks.ok(&check, path),
+            Some(path) if is_required => checks.error(&check, format!("{} doesn't exist", path)),
+            Some(path) => checks.warning(&check, format!("{} doesn't exist yet", path)),
+            None => checks.error(&check, "not configured"),
+        }
+    }
+
+    let template = config.get_site_directory_template();
+    let unknown_variables = get_unknown_variables(template);
+    if unknown_variables.is_empty() {
+        checks.ok("site_directory_template", template);
+    } else {
+        let message = format!("unknown variables {}", unknown_variables.join(", "));
+        checks.error("site_directory_template", message);
+    }
+
+    for (name, token) in config.get_tokens() {
+        let check = format!("token {}", name);
+
+        if token.hash.len() != 64 || !token.hash.chars().all(|c| c.is_ascii_hexdigit()) {
+            checks.error(&check, "hash isn't a SHA-256 hash in hexadecimal");
+        } else {
+            checks.ok(&check, "valid hash");
+        }
+    }
+
+    let mut sites: Vec<_> = config.get_sites_config().iter().collect();
+    sites.sort_by_key(|(key, _)| *key);
+
+    for (key, site) in sites {
+        let check = format!("sites.{}", key);
+        let mut problems = Vec::new();
+        let mut warnings = Vec::new();
+
+        if let Err(error) = Pattern::new(key) {
+            problems.push(format!("invalid pattern: {}", error));
+        }
+
+        let users = [site.user.is_some(), site.uid.is_some(), site.run_as_owner];
+        if users.iter().filter(|&&is_set| is_set).count() > 1 {
+            problems.push("only one of user, uid and run_as_owner can be set".to_string());
+        }
+
+        if site.gid.is_some() && site.uid.is_none() {
+            problems.push("gid is only used with uid".to_string());
+        }
+
+        // The site directory owner is checked for each site
+        match site.get_user() {
+            Some(SiteUser::Name(name)) => match RunAs::from_user_name(&name) {
+                None => problems.push(format!("unknown user {}", name)),
+                Some(run_as) if run_as.uid == 0 => {
+                    warnings.push(format!(
+                        "user {} is root, recipes can modify every site",
+                        name
+                    ));
+                }
+                Some(_) => {}
+            },
+            Some(SiteUser::Id { uid: 0, .. }) => {
+                warnings.push("uid 0 is root, recipes can modify every site".to_string());
+            }
+            Some(SiteUser::Id { uid, gid: None }) if RunAs::from_uid(uid).is_none() => {
+                problems.push(format!("unknown uid {}, gid must be set", uid));
+            }
+            _ => {}
+        }
+
+        if let Some(sandbox) = &site.sandbox {
+            for directory in sandbox.path.split(':') {
+                if !Path::new(directory).is_absolute() {
+                    problems.push(format!("sandbox PATH entry {} isn't absolute", directory));
+                }
+            }
+
+            for limit in sandbox.limits.get_zero_limits() {
+                problems.push(format!("sandbox limit {} can't be 0", limit));
+            }
+        }
+
+        for action in site.actions.iter().flatten() {
+            if !is_valid_action_name(action) {
+                problems.push(format!("invalid action name {}", action));
+            }
+        }
+
+        if !problems.is_empty() {
+            checks.error(&check, problems.join(", "));
+        } else if !warnings.is_empty() {
+            checks.warning(&check, warnings.join(", "));
+        } else {
+            checks.ok(&check, "valid");
+        }
+    }
+
+    checks.0
+}
+
+fn get_unknown_variables(template: &str) -> Vec<String> {
+    template
+        .split('%')
+        .skip(1)
+        .step_by(2)
+        .filter(|variable| !["fqdn", "subdomain", "domain", "tld"].contains(variable))
+        .map(String::from)
+        .collect()
+}
+
+fn check_site(config: &AlkaneConfig, site_name: &str) -> Vec<CheckResult> {
+    let mut checks = Checks::default();
+
+    // The site name is used to build the recipes and site paths
+    if !is_valid_site_name(site_name) {
+        checks.error("name", "invalid site name");
+        return checks.0;
+    }
+
+    if config.uses_domain_parts() {
+        match extract_domain_parts(site_name) {
+            Some((subdomain, domain, tld)) => {
+                let message = format!("subdomain {}, domain {}, tld {}", subdomain, domain, tld);
+                checks.ok("domain", message);
+            }
+            None => checks.error("domain", "can't extract subdomain, domain and tld"),
+        }
+    }
+
+    let site = match config.get_site(site_name, None) {
+        Some(site) => site,
+        None => {
+            checks.error("path", "can't resolve site path");
+            return checks.0;
+        }
+    };
+
+    match Path::new(&site.path).parent() {
+        Some(parent) if parent.is_dir() => checks.ok("path", &site.path),
+        _ => checks.error(
+            "path",
+            format!("{}: parent directory doesn't exist", site.path),
+        ),
+    }
+
+    if let Some(user) = config.get_site_user(site_name) {
+        match RunAs::resolve(&user, &site.path) {
+            Ok(run_as) => {
+                let message = format!("{} (uid {}, gid {})", user, run_as.uid, run_as.gid);
+                checks.ok("user", message);
+            }
+            // The site directory could be created before init
+            Err(error) if user == SiteUser::Owner && !Path::new(&site.path).exists() => {
+                checks.warning("user", error);
+            }
+            Err(error) => checks.error("user", error),
+        }
+    }
+
+    let recipes = match RecipesStore::from_config(config) {
+        Some(recipes) => recipes,
+        None => {
+            checks.error("recipes", "can't initialize recipes store");
+            return checks.0;
+        }
+    };
+
+    let mut recipe_names = recipes.get_recipes(&site);
+    for required in REQUIRED_RECIPES {
+        if !recipe_names.iter().any(|name| name == required) {
+            checks.error(&format!("recipe {}", required), "missing");
+        }
+    }
+
+    recipe_names.sort();
+    for name in recipe_names {
+        let path = recipes.get_recipe_path(&site, &name);
+        check_recipe(&mut checks, &name, &path);
+    }
+
+    checks.0
+}
+
+/// Checks a recipe can run, and can't be modified by untrusted users
+fn check_recipe(checks: &mut Checks, name: &str, path: &str) {
+    let check = format!("recipe {}", name);
+
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(error) => {
+            checks.error(&check, format!("can't read metadata: {}", error));
+            return;
+        }
+    };
+
+    let mode = metadata.permissions().mode();
+    let trusted_uids = [0, unsafe { libc::geteuid() }];
+    let mut problems = Vec::new();
+
+    if mode & 0o111 == 0 {
+        problems.push("not executable".to_string());
+    }
+
+    if !trusted_uids.contains(&metadata.uid()) {
+        problems.push(format!("owned by untrusted uid {}", metadata.uid()));
+    }
+
+    if mode & 0o002 != 0 {
+        problems.push("world-writable".to_string());
+    }
+
+    if !problems.is_empty() {
+        checks.error(&check, problems.join(", "));
+    } else if mode & 0o020 != 0 {
+        checks.warning(&check, "group-writable");
+    } else {
+        checks.ok(&check, path);
+    }
+}
+
+//  -------------------------------------------------------------
+//  Tests
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_get_unknown_variables() {
+        assert!(get_unknown_variables("%domain%.%tld%/%subdomain%").is_empty());
+        assert!(get_unknown_variables("%fqdn%").is_empty());
+        assert_eq!(vec!["sub"], get_unknown_variables("%domain%/%sub%"));
+    }
+
+    #[test]
+    pub fn test_check_recipe() {
+        let directory =
+            std::env::temp_dir().join(format!("alkane-test-check-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("update");
+        fs::write(&path, "#!/bin/sh\n").unwrap();
+        let path = path.to_str().unwrap();
+
+        let mut checks = Checks::default();
+
+        fs::set_permissions(path, fs::Permissions::from_mode(0o755)).unwrap();
+        check_recipe(&mut checks, "update", path);
+        fs::set_permissions(path, fs::Permissions::from_mode(0o775)).unwrap();
+        check_recipe(&mut checks, "update", path);
+        fs::set_permissions(path, fs::Permissions::from_mode(0o646)).unwrap();
+        check_recipe(&mut checks, "update", path);
+
+        let levels: Vec<_> = checks.0.iter().map(|result| result.level).collect();
+        assert_eq!(
+            vec![CheckLevel::Ok, CheckLevel::Warning, CheckLevel::Error],
+            levels
+        );
+        assert_eq!("not executable, world-writable", checks.0[2].message);
+
+        fs::remove_dir_all(directory).expect("Can't remove temporary directory.")
+    }
+
+    #[test]
+    pub fn test_check_config_sites() {
+        let yaml = r#"
+roots: {}
+site_directory_template: "%fqdn%"
+sites:
+  both.acme.tld:
+    user: root
+    run_as_owner: true
+  gid.acme.tld:
+    gid: 1000
+  id.acme.tld:
+    uid: 4242
+    gid: 4242
+  root.acme.tld:
+    user: root
+  root-id.acme.tld:
+    uid: 0
+  sandbox.acme.tld:
+    sandbox:
+      path: /usr/bin:bin
+      limits:
+        processes: 0
+"#;
+        let config: AlkaneConfig = serde_yaml::from_str(yaml).unwrap();
+
+        let results: BTreeMap<_, _> = check_config(&config)
+            .into_iter()
+            .filter(|result| result.check.starts_with("sites."))
+            .map(|result| (result.check.clone(), result))
+            .collect();
+        let messages: BTreeMap<_, _> = results
+            .iter()
+            .map(|(check, result)| (check.as_str(), result.message.as_str()))
+            .collect();
+
+        assert_eq!(
+            "only one of user, uid and run_as_owner can be set",
+            messages["sites.both.acme.tld"]
+        );
+        assert_eq!("gid is only used with uid", messages["sites.gid.acme.tld"]);
+        assert_eq!("valid", messages["sites.id.acme.tld"]);
+        assert_eq!(
+            "user root is root, recipes can modify every site",
+            messages["sites.root.acme.tld"]
+        );
+        assert_eq!(
+            "uid 0 is root, recipes can modify every site",
+            messages["sites.root-id.acme.tld"]
+        );
+        assert_eq!(CheckLevel::Warning, results["sites.root.acme.tld"].level);
+        assert_eq!(CheckLevel::Error, results["sites.both.acme.tld"].level);
+        assert_eq!(
+            "sandbox PATH entry bin isn't absolute, sandbox limit processes can't be 0",
+            messages["sites.sandbox.acme.tld"]
+        );
+    }
+
+    #[test]
+    pub fn test_check() {
+        let config = AlkaneConfig::load().unwrap();
+
+        // Recipes can be group-writable according to the checkout umask
+        let report = check(&config, &["foo.acme.tld".to_string()]);
+        assert!(report.get_level() < CheckLevel::Error);
+        assert!(report
+            .config
+            .iter()
+            .all(|result| result.level == CheckLevel::Ok));
+
+        let report = check(&config, &["acme.tld".to_string()]);
+        assert_eq!(CheckLevel::Error, report.get_level());
+        assert_eq!(2, report.to_status_code());
+
+        let report = check(&config, &["../../etc".to_string()]);
+        let results = &report.sites["../../etc"];
+        assert_eq!(1, results.len());
+        assert_eq!("name", results[0].check);
+        assert_eq!(CheckLevel::Error, results[0].level);
+    }
+}
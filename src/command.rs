@@ -30,9 +30,38 @@ pub enum AlkaneCommand {
     #[command(arg_required_else_help = true)]
     Deploy(DeployArgs),
 
+    /// Run a custom recipe for a site, like clear-cache or reindex
+    #[command(arg_required_else_help = true)]
+    Run(RunArgs),
+
+    /// Decommission a site, deleting its directory and state
+    #[command(arg_required_else_help = true)]
+    Remove(RemoveArgs),
+
+    /// Point a site using the releases layout to a previous release
+    #[command(arg_required_else_help = true)]
+    Rollback(RollbackArgs),
+
+    /// List the sites known by Alkane
+    List(ListArgs),
+
+    /// Validate the configuration and the recipes of a site or of all sites
+    Check(CheckArgs),
+
+    /// Determine if Alkane can deploy sites: configuration, roots, database and disk space
+    Health(HealthArgs),
+
     /// Determine if a domain is served on our PaaS
     #[command(name = "is-present", arg_required_else_help = true)]
     IsPresent(IsPresentArgs),
+
+    /// Show the deployments journal of a site
+    #[command(arg_required_else_help = true)]
+    History(HistoryArgs),
+
+    /// Show the output of a recipe run
+    #[command(arg_required_else_help = true)]
+    Logs(LogsArgs),
 }
 
 //  -------------------------------------------------------------
@@ -46,6 +75,68 @@ pub struct DeployArgs {
 
     /// The artifact to deploy. Allows CD to give metadata or a URL to download last artifact
     pub artifact: Option<String>,
+
+    /// Show what would be done, without running anything
+    #[arg(long, default_value_t = false)]
+    pub dry_run: bool,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct RunArgs {
+    /// The name of the site, using sub.domain.tld format
+    pub site_name: String,
+
+    /// The action to run, named after the recipe script
+    pub action: String,
+
+    /// Show what would be done, without running anything
+    #[arg(long, default_value_t = false)]
+    pub dry_run: bool,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct OutputArgs {
+    /// Don't print the recipe output while it runs
+    #[arg(short, long, default_value_t = false)]
+    pub quiet: bool,
+
+    /// Append the recipe output to this file while it runs
+    #[arg(long)]
+    pub log_file: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct RemoveArgs {
+    /// Confirm the site should be removed. Required.
+    #[arg(long, default_value_t = false)]
+    pub confirm: bool,
+
+    /// Keep the site directory, only forget the site state
+    #[arg(long, default_value_t = false, conflicts_with = "delete")]
+    pub keep_files: bool,
+
+    /// Delete the site directory instead of moving it under the archives root
+    #[arg(long, default_value_t = false)]
+    pub delete: bool,
+
+    /// The name of the site to remove, using sub.domain.tld format
+    pub site_name: String,
+}
+
+#[derive(Debug, Args)]
+pub struct RollbackArgs {
+    /// The release to roll back to. By default, the release before the current one.
+    #[arg(long)]
+    pub to: Option<String>,
+
+    /// The name of the site to roll back, using sub.domain.tld format
+    pub site_name: String,
 }
 
 #[derive(Debug, Args)]
@@ -57,6 +148,50 @@ pub struct IsPresentArgs {
     pub site_name: String,
 }
 
+#[derive(Debug, Args)]
+pub struct ListArgs {
+    /// Print the list as JSON
+    #[arg(long, default_value_t = false)]
+    pub json: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct CheckArgs {
+    /// Print the report as JSON
+    #[arg(long, default_value_t = false)]
+    pub json: bool,
+
+    /// The name of the site to check, using sub.domain.tld format. By default, all known sites.
+    pub site_name: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct HealthArgs {
+    /// Print the report as JSON
+    #[arg(long, default_value_t = false)]
+    pub json: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct HistoryArgs {
+    /// Print the journal as JSON
+    #[arg(long, default_value_t = false)]
+    pub json: bool,
+
+    /// The name of the site, using sub.domain.tld format
+    pub site_name: String,
+}
+
+#[derive(Debug, Args)]
+pub struct LogsArgs {
+    /// The run identifier, as shown in the history. By default, the last run.
+    #[arg(short, long)]
+    pub run: Option<u64>,
+
+    /// The name of the site, using sub.domain.tld format
+    pub site_name: String,
+}
+
 //  -------------------------------------------------------------
 //  Helper methods
 //  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
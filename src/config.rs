@@ -6,14 +6,17 @@
 //  -------------------------------------------------------------
 
 use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
 use std::fs::File;
 use std::path::{Path, MAIN_SEPARATOR_STR};
+use std::time::Duration;
 
+use glob::Pattern;
 use lazy_static::lazy_static;
-use log::info;
+use log::{info, warn};
 use serde::Deserialize;
-use serde_yaml;
 
+use crate::runner::sandbox::Sandbox;
 use crate::runner::site::Site;
 use crate::services::tld::extract_domain_parts;
 
@@ -45,20 +48,180 @@ lazy_static! {
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct AlkaneConfig {
+    /// The path of the configuration file loaded
+    #[serde(skip)]
+    path: Option<String>,
+
     /// The paths to the root directories used by Alkane
     roots: HashMap<String, String>,
 
     /// The template for a site directory
     site_directory_template: String,
+
+    /// The number of recipe runs to keep the output for each site
+    #[serde(default = "default_logs_retention")]
+    logs_retention: usize,
+
+    /// How long to wait, in seconds, when another deployment runs for a site
+    #[serde(default)]
+    lock_timeout: u64,
+
+    /// How long a recipe can run, in seconds, before being killed
+    #[serde(default)]
+    recipe_timeout: Option<u64>,
+
+    /// The number of releases to keep for sites using the releases layout
+    #[serde(default = "default_releases_retention")]
+    releases_retention: usize,
+
+    /// Settings specific to a site, keyed by site name or glob pattern
+    #[serde(default)]
+    sites: HashMap<String, SiteConfig>,
+
+    /// How old, in seconds, a signed webhook request can be
+    #[serde(default = "default_webhook_tolerance")]
+    webhook_tolerance: u64,
+
+    /// The free space needed under the sites root to be healthy, in MiB
+    #[serde(default = "default_min_free_space")]
+    min_free_space: u64,
+
+    /// Tokens allowed to use the HTTP API, keyed by a descriptive name.
+    /// If no token is configured, the HTTP API doesn't require authentication.
+    #[serde(default)]
+    tokens: HashMap<String, TokenConfig>,
+}
+
+/// Represents the settings of a site, overriding the global ones
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct SiteConfig {
+    /// The site directory, absolute or relative to the sites root,
+    /// instead of the one resolved from site_directory_template
+    pub path: Option<String>,
+
+    /// The directory containing the site recipes,
+    /// instead of <recipes root>/<site name>
+    pub recipes: Option<String>,
+
+    /// Additional environment variables given to the recipes
+    #[serde(default)]
+    pub environment: HashMap<String, String>,
+
+    /// The user to run the recipes as
+    pub user: Option<String>,
+
+    /// The user ID to run the recipes as, instead of a user name
+    pub uid: Option<u32>,
+
+    /// The group ID to run the recipes as, by default the primary group of uid
+    pub gid: Option<u32>,
+
+    /// If true, run the recipes as the owner of the site directory
+    #[serde(default)]
+    pub run_as_owner: bool,
+
+    /// If set, the recipes run with a restricted environment and resources
+    pub sandbox: Option<Sandbox>,
+
+    /// If set, only these actions can be run for the site, like "update"
+    pub actions: Option<Vec<String>>,
+
+    /// How long a recipe can run, in seconds, 0 to disable the timeout
+    pub timeout: Option<u64>,
+
+    /// If true, each deployment lands in a new release directory,
+    /// and the live content is a symbolic link to the current release
+    #[serde(default)]
+    pub releases: bool,
+
+    /// If set, deployment requests through the HTTP API
+    /// must be signed with HMAC-SHA256 using this secret
+    pub webhook_secret: Option<String>,
+
+    /// If set, GitLab push webhooks must send this token in X-Gitlab-Token.
+    /// As GitLab sends it in plain text, it's never used as a HMAC key.
+    pub gitlab_token: Option<String>,
+
+    /// The Git repository deployed on push, as full name like "acme/foo"
+    pub repository: Option<String>,
+
+    /// The branch deployed on push, by default the repository default branch
+    pub branch: Option<String>,
+}
+
+/// Represents the user to run the recipes of a site as
+#[derive(Clone, Debug, PartialEq)]
+pub enum SiteUser {
+    /// A user name, resolved from the system users database
+    Name(String),
+
+    /// A user ID, with a group ID if the primary group of the user isn't wanted
+    Id { uid: u32, gid: Option<u32> },
+
+    /// The owner of the site directory
+    Owner,
+}
+
+impl Display for SiteUser {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SiteUser::Name(name) => write!(f, "{}", name),
+            SiteUser::Id { uid, gid: None } => write!(f, "uid {}", uid),
+            SiteUser::Id {
+                uid,
+                gid: Some(gid),
+            } => write!(f, "uid {}, gid {}", uid, gid),
+            SiteUser::Owner => write!(f, "owner of the site directory"),
+        }
+    }
+}
+
+impl SiteConfig {
+    pub fn get_user(&self) -> Option<SiteUser> {
+        if let Some(name) = &self.user {
+            return Some(SiteUser::Name(name.clone()));
+        }
+
+        if let Some(uid) = self.uid {
+            return Some(SiteUser::Id { uid, gid: self.gid });
+        }
+
+        self.run_as_owner.then_some(SiteUser::Owner)
+    }
 }
 
 #[derive(Debug)]
 pub enum AlkaneConfigError {
     IO(std::io::Error),
-    YAML(serde_yaml::Error),
+    Yaml(serde_yaml::Error),
     FileNotFound,
 }
 
+impl Display for AlkaneConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AlkaneConfigError::IO(error) => write!(f, "I/O error: {}", error),
+            AlkaneConfigError::Yaml(error) => write!(f, "YAML error: {}", error),
+            AlkaneConfigError::FileNotFound => write!(f, "configuration file not found"),
+        }
+    }
+}
+
+/// Represents a bearer token allowed to use the HTTP API
+#[derive(Clone, Debug, Deserialize)]
+pub struct TokenConfig {
+    /// The SHA-256 hash of the token, in hexadecimal
+    pub hash: String,
+
+    /// The sites the token can act on, as names or glob patterns
+    #[serde(default = "default_token_scope")]
+    pub sites: Vec<String>,
+
+    /// The actions the token can request, like "update" or "history"
+    #[serde(default = "default_token_scope")]
+    pub actions: Vec<String>,
+}
+
 impl AlkaneConfig {
     pub fn load() -> Result<Self, AlkaneConfigError> {
         match Self::find() {
@@ -68,7 +231,11 @@ impl AlkaneConfig {
 
                 let file = File::open(&path).map_err(AlkaneConfigError::IO)?;
 
-                serde_yaml::from_reader(file).map_err(AlkaneConfigError::YAML)
+                let mut config: Self =
+                    serde_yaml::from_reader(file).map_err(AlkaneConfigError::Yaml)?;
+                config.path = Some(path);
+
+                Ok(config)
             }
         }
     }
@@ -81,19 +248,180 @@ impl AlkaneConfig {
             .next()
     }
 
+    /// Gets the path of the configuration file loaded
+    pub fn get_path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+
     pub fn get_root(&self, key: &str) -> Option<String> {
         if self.roots.contains_key(key) {
-            self.roots.get(key).map(|s| String::from(s))
+            self.roots.get(key).map(String::from)
         } else {
             ROOTS.get(key).map(|s| String::from(*s))
         }
     }
 
+    /// Gets where to archive removed sites, by default <sites root>/.archives
+    pub fn get_archives_root(&self) -> Option<String> {
+        match self.roots.get("archives") {
+            Some(root) => Some(root.clone()),
+            None => self
+                .get_root("sites")
+                .map(|root| Path::new(&root).join(".archives"))
+                .and_then(|path| path.to_str().map(String::from)),
+        }
+    }
+
+    pub fn get_logs_retention(&self) -> usize {
+        self.logs_retention
+    }
+
+    pub fn get_lock_timeout(&self) -> Duration {
+        Duration::from_secs(self.lock_timeout)
+    }
+
+    /// Gets the free space needed under the sites root, in bytes
+    pub fn get_min_free_space(&self) -> u64 {
+        self.min_free_space * 1024 * 1024
+    }
+
+    /// Gets the settings of a site, by name, or else by the most specific
+    /// glob pattern matching it, like "*.acme.tld"
+    pub fn get_site_config(&self, site_name: &str) -> Option<&SiteConfig> {
+        if let Some(site) = self.sites.get(site_name) {
+            return Some(site);
+        }
+
+        self.sites
+            .iter()
+            .filter(|(pattern, _)| is_pattern(pattern))
+            .filter(|(pattern, _)| match Pattern::new(pattern) {
+                Ok(pattern) => pattern.matches(site_name),
+                Err(error) => {
+                    warn!("Invalid site pattern '{}': {}", pattern, error);
+
+                    false
+                }
+            })
+            .max_by(|(a, _), (b, _)| a.len().cmp(&b.len()).then(b.cmp(a)))
+            .map(|(_, site)| site)
+    }
+
+    /// Determines if the site directory is set by a glob pattern section,
+    /// so is shared by every site matching it
+    pub fn is_site_path_shared(&self, site_name: &str) -> bool {
+        !self.sites.contains_key(site_name)
+            && self
+                .get_site_config(site_name)
+                .is_some_and(|site| site.path.is_some())
+    }
+
+    /// Determines if the action can be run for the site
+    pub fn is_action_enabled(&self, site_name: &str, action: &str) -> bool {
+        match self
+            .get_site_config(site_name)
+            .and_then(|site| site.actions.as_ref())
+        {
+            Some(actions) => actions.iter().any(|candidate| candidate == action),
+            None => true,
+        }
+    }
+
+    /// Gets the user to run the site recipes as, if configured.
+    /// A user name takes precedence over uid, then over run_as_owner.
+    pub fn get_site_user(&self, site_name: &str) -> Option<SiteUser> {
+        self.get_site_config(site_name)
+            .and_then(SiteConfig::get_user)
+    }
+
+    /// Gets the sandbox to run the site recipes in, if enabled
+    pub fn get_site_sandbox(&self, site_name: &str) -> Option<Sandbox> {
+        self.get_site_config(site_name)
+            .and_then(|site| site.sandbox.clone())
+    }
+
+    /// Gets how long a recipe can run for the site, None if there is no limit
+    pub fn get_recipe_timeout(&self, site_name: &str) -> Option<Duration> {
+        self.get_site_config(site_name)
+            .and_then(|site| site.timeout)
+            .or(self.recipe_timeout)
+            .filter(|&seconds| seconds > 0)
+            .map(Duration::from_secs)
+    }
+
+    pub fn uses_releases(&self, site_name: &str) -> bool {
+        self.get_site_config(site_name)
+            .map(|site| site.releases)
+            .unwrap_or(false)
+    }
+
+    pub fn get_releases_retention(&self) -> usize {
+        self.releases_retention
+    }
+
+    pub fn get_webhook_secret(&self, site_name: &str) -> Option<&str> {
+        self.get_site_config(site_name)
+            .and_then(|site| site.webhook_secret.as_deref())
+    }
+
+    pub fn get_gitlab_token(&self, site_name: &str) -> Option<&str> {
+        self.get_site_config(site_name)
+            .and_then(|site| site.gitlab_token.as_deref())
+    }
+
+    pub fn get_webhook_tolerance(&self) -> Duration {
+        Duration::from_secs(self.webhook_tolerance)
+    }
+
+    /// Finds the sites to deploy when a branch is pushed to a repository
+    pub fn get_sites_for_push(
+        &self,
+        repository: &str,
+        branch: &str,
+        default_branch: Option<&str>,
+    ) -> Vec<String> {
+        let mut sites: Vec<String> = self
+            .sites
+            .iter()
+            .filter(|(site_name, _)| !is_pattern(site_name))
+            .filter(|(_, site)| match &site.repository {
+                Some(site_repository) => site_repository.eq_ignore_ascii_case(repository),
+                None => false,
+            })
+            .filter(
+                |(_, site)| match site.branch.as_deref().or(default_branch) {
+                    Some(site_branch) => site_branch == branch,
+                    None => false,
+                },
+            )
+            .map(|(site_name, _)| site_name.clone())
+            .collect();
+
+        sites.sort();
+        sites
+    }
+
+    pub fn get_tokens(&self) -> &HashMap<String, TokenConfig> {
+        &self.tokens
+    }
+
+    /// Gets the site sections, by site name or glob pattern
+    pub fn get_sites_config(&self) -> &HashMap<String, SiteConfig> {
+        &self.sites
+    }
+
     pub fn get_site(&self, site_name: &str, context: Option<String>) -> Option<Site> {
+        let site_config = self.get_site_config(site_name);
+
         self.get_site_path(site_name).map(|path| Site {
             name: site_name.to_string(),
             context,
             path,
+            revision: None,
+            recipes_path: site_config.and_then(|site| site.recipes.clone()),
+            environment: site_config
+                .map(|site| site.environment.clone())
+                .unwrap_or_default(),
         })
     }
 
@@ -101,12 +429,79 @@ impl AlkaneConfig {
         let root = self.get_root("sites")?;
         let root = root.replace("/", MAIN_SEPARATOR_STR);
 
-        let subdir = self.resolve_site_subdir(site_name)?;
+        let subdir = self.get_site_subdir(site_name)?;
+
+        Path::new(&root).join(&subdir).to_str().map(String::from)
+    }
+
+    /// Gets the site directory relative to the sites root, as configured
+    /// for the site, or else resolved from the site directory template
+    pub fn get_site_subdir(&self, site_name: &str) -> Option<String> {
+        let configured_path = self
+            .get_site_config(site_name)
+            .and_then(|site| site.path.as_ref());
 
-        Path::new(&root)
-            .join(&subdir)
-            .to_str()
-            .map(|path| String::from(path))
+        match configured_path {
+            Some(path) => Some(path.replace("/", MAIN_SEPARATOR_STR)),
+            None => self.resolve_site_subdir(site_name),
+        }
+    }
+
+    pub fn get_site_directory_template(&self) -> &str {
+        &self.site_directory_template
+    }
+
+    /// Determines if resolving a site directory requires to extract
+    /// the subdomain, domain or tld from the site name
+    pub fn uses_domain_parts(&self) -> bool {
+        contains_domain_parts_variables(&self.site_directory_template)
+    }
+
+    /// Gets the names of the sites declared in the configuration,
+    /// excluding glob patterns
+    pub fn get_configured_sites(&self) -> Vec<String> {
+        self.sites
+            .keys()
+            .filter(|key| !is_pattern(key))
+            .cloned()
+            .collect()
+    }
+
+    /// Gets how many directories deep a site directory is under the sites root
+    pub fn get_site_directory_depth(&self) -> usize {
+        self.site_directory_template.split('/').count()
+    }
+
+    /// Finds the site a directory under the sites root belongs to,
+    /// by matching it against the site directory template.
+    ///
+    /// This is the reverse operation of resolve_site_subdir.
+    pub fn find_site_name(&self, subdir: &str) -> Option<String> {
+        let mut candidates = Vec::new();
+        match_template(
+            &self.site_directory_template,
+            subdir,
+            &mut HashMap::new(),
+            &mut candidates,
+        );
+
+        candidates
+            .into_iter()
+            .map(|variables| match variables.get("fqdn") {
+                Some(fqdn) => fqdn.clone(),
+                None => ["subdomain", "domain", "tld"]
+                    .iter()
+                    .filter_map(|variable| variables.get(*variable))
+                    .filter(|value| !value.is_empty())
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .join("."),
+            })
+            .find(|site_name| {
+                self.resolve_site_subdir(site_name)
+                    .map(|candidate| candidate.replace(MAIN_SEPARATOR_STR, "/") == subdir)
+                    .unwrap_or(false)
+            })
     }
 
     fn resolve_site_subdir(&self, site_name: &str) -> Option<String> {
@@ -129,6 +524,88 @@ impl AlkaneConfig {
     }
 }
 
+fn default_logs_retention() -> usize {
+    10
+}
+
+fn default_releases_retention() -> usize {
+    5
+}
+
+fn default_webhook_tolerance() -> u64 {
+    300
+}
+
+fn default_min_free_space() -> u64 {
+    1024
+}
+
+fn default_token_scope() -> Vec<String> {
+    vec!["*".to_string()]
+}
+
+/// Finds every way the template variables can be bound to match the value.
+/// A variable matches a non-empty string without directory separator.
+fn match_template(
+    template: &str,
+    value: &str,
+    variables: &mut HashMap<String, String>,
+    candidates: &mut Vec<HashMap<String, String>>,
+) {
+    if template.is_empty() {
+        if value.is_empty() {
+            candidates.push(variables.clone());
+        }
+
+        return;
+    }
+
+    let variable = ["fqdn", "subdomain", "domain", "tld"]
+        .into_iter()
+        .find(|variable| template.starts_with(&format!("%{}%", variable)));
+
+    let variable = match variable {
+        Some(variable) => variable,
+        None => {
+            // Literal character
+            let c = template.chars().next().unwrap();
+            if let Some(rest) = value.strip_prefix(c) {
+                match_template(&template[c.len_utf8()..], rest, variables, candidates);
+            }
+
+            return;
+        }
+    };
+
+    let template = &template[variable.len() + 2..];
+
+    if let Some(bound) = variables.get(variable).cloned() {
+        if let Some(rest) = value.strip_prefix(bound.as_str()) {
+            match_template(template, rest, variables, candidates);
+        }
+
+        return;
+    }
+
+    let max = value.find('/').unwrap_or(value.len());
+    let ends = value[..max]
+        .char_indices()
+        .map(|(index, _)| index)
+        .skip(1)
+        .chain([max]);
+
+    for end in ends {
+        variables.insert(variable.to_string(), value[..end].to_string());
+        match_template(template, &value[end..], variables, candidates);
+        variables.remove(variable);
+    }
+}
+
+/// Determines if a site key is a glob pattern rather than a site name
+fn is_pattern(key: &str) -> bool {
+    key.contains(['*', '?', '['])
+}
+
 //  -------------------------------------------------------------
 //  Helper methods to extract domain name parts
 //  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
@@ -163,6 +640,8 @@ mod tests {
             &config.site_directory_template,
             "%domain%.%tld%/%subdomain%"
         );
+        assert_eq!(10, config.get_logs_retention());
+        assert_eq!(Some(".alkane.conf"), config.get_path());
     }
 
     #[test]
@@ -189,12 +668,225 @@ mod tests {
     }
 
     #[test]
-    pub fn test_contains_domain_parts_variables() {
+    pub fn test_get_recipe_timeout() {
+        let yaml = r#"
+roots: {}
+site_directory_template: "%fqdn%"
+recipe_timeout: 600
+sites:
+  slow.acme.tld:
+    timeout: 3600
+  unlimited.acme.tld:
+    timeout: 0
+"#;
+        let config: AlkaneConfig = serde_yaml::from_str(yaml).unwrap();
+
+        assert_eq!(
+            Some(Duration::from_secs(600)),
+            config.get_recipe_timeout("foo.acme.tld")
+        );
+        assert_eq!(
+            Some(Duration::from_secs(3600)),
+            config.get_recipe_timeout("slow.acme.tld")
+        );
+        assert_eq!(None, config.get_recipe_timeout("unlimited.acme.tld"));
+    }
+
+    #[test]
+    pub fn test_get_site_config_by_pattern() {
+        let yaml = r#"
+roots:
+  sites: /var/wwwroot
+site_directory_template: "%fqdn%"
+sites:
+  "*.acme.tld":
+    timeout: 600
+    actions:
+      - update
+  "*.static.acme.tld":
+    timeout: 60
+  "*.shared.acme.tld":
+    path: /srv/shared
+  legacy.acme.tld:
+    path: /srv/legacy
+    recipes: /srv/legacy/recipes
+    environment:
+      APP_ENV: production
+"#;
+        let config: AlkaneConfig = serde_yaml::from_str(yaml).unwrap();
+
+        // The most specific pattern wins, an exact name wins over patterns
+        assert_eq!(
+            Some(Duration::from_secs(600)),
+            config.get_recipe_timeout("foo.acme.tld")
+        );
+        assert_eq!(
+            Some(Duration::from_secs(60)),
+            config.get_recipe_timeout("foo.static.acme.tld")
+        );
+        assert_eq!(None, config.get_recipe_timeout("legacy.acme.tld"));
+        assert!(config.get_site_config("foo.example.org").is_none());
+
+        assert!(config.is_action_enabled("foo.acme.tld", "update"));
+        assert!(!config.is_action_enabled("foo.acme.tld", "init"));
+        assert!(config.is_action_enabled("legacy.acme.tld", "init"));
+
+        let site = config.get_site("legacy.acme.tld", None).unwrap();
+        assert_eq!("/srv/legacy", site.path);
+        assert_eq!(Some("/srv/legacy/recipes".to_string()), site.recipes_path);
+        assert_eq!("production", site.environment["APP_ENV"]);
+
+        assert!(config.is_site_path_shared("foo.shared.acme.tld"));
+        assert!(!config.is_site_path_shared("legacy.acme.tld"));
+        assert!(!config.is_site_path_shared("foo.acme.tld"));
+    }
+
+    #[test]
+    pub fn test_get_site_user() {
+        let yaml = r#"
+roots: {}
+site_directory_template: "%fqdn%"
+sites:
+  named.acme.tld:
+    user: www-deploy
+    uid: 1001
+  id.acme.tld:
+    uid: 1001
+    gid: 1002
+  owned.acme.tld:
+    run_as_owner: true
+"#;
+        let config: AlkaneConfig = serde_yaml::from_str(yaml).unwrap();
+
+        assert_eq!(
+            Some(SiteUser::Name("www-deploy".to_string())),
+            config.get_site_user("named.acme.tld")
+        );
+        assert_eq!(
+            Some(SiteUser::Id {
+                uid: 1001,
+                gid: Some(1002)
+            }),
+            config.get_site_user("id.acme.tld")
+        );
+        assert_eq!(
+            Some(SiteUser::Owner),
+            config.get_site_user("owned.acme.tld")
+        );
+        assert_eq!(None, config.get_site_user("foo.acme.tld"));
+    }
+
+    #[test]
+    pub fn test_get_site_sandbox() {
+        let yaml = r#"
+roots: {}
+site_directory_template: "%fqdn%"
+sites:
+  quoted.acme.tld:
+    sandbox:
+      umask: "027"
+  unquoted.acme.tld:
+    sandbox:
+      umask: 027
+  short.acme.tld:
+    sandbox:
+      umask: 27
+"#;
+        let config: AlkaneConfig = serde_yaml::from_str(yaml).unwrap();
+
+        for site_name in ["quoted.acme.tld", "unquoted.acme.tld", "short.acme.tld"] {
+            let sandbox = config.get_site_sandbox(site_name).unwrap();
+            assert_eq!(0o027, sandbox.umask, "umask of {}", site_name);
+        }
+        assert_eq!(None, config.get_site_sandbox("foo.acme.tld"));
+    }
+
+    #[test]
+    pub fn test_get_sites_for_push() {
+        let yaml = r#"
+roots: {}
+site_directory_template: "%fqdn%"
+sites:
+  foo.acme.tld:
+    repository: acme/foo
+  foo-dev.acme.tld:
+    repository: acme/foo
+    branch: develop
+  bar.acme.tld:
+    timeout: 600
+"#;
+        let config: AlkaneConfig = serde_yaml::from_str(yaml).unwrap();
+
+        assert_eq!(
+            vec!["foo.acme.tld"],
+            config.get_sites_for_push("Acme/Foo", "main", Some("main"))
+        );
         assert_eq!(
-            true,
-            contains_domain_parts_variables("%domain%/%subdomain%")
+            vec!["foo-dev.acme.tld"],
+            config.get_sites_for_push("acme/foo", "develop", Some("main"))
         );
-        assert_eq!(false, contains_domain_parts_variables("%fqdn%"));
-        assert_eq!(false, contains_domain_parts_variables(""));
+        assert!(config
+            .get_sites_for_push("acme/foo", "feature", Some("main"))
+            .is_empty());
+        assert!(config
+            .get_sites_for_push("acme/bar", "main", Some("main"))
+            .is_empty());
+    }
+
+    #[test]
+    pub fn test_get_gitlab_token() {
+        let yaml = r#"
+roots: {}
+site_directory_template: "%fqdn%"
+sites:
+  foo.acme.tld:
+    webhook_secret: s3cret
+    gitlab_token: t0ken
+  bar.acme.tld:
+    webhook_secret: s3cret
+"#;
+        let config: AlkaneConfig = serde_yaml::from_str(yaml).unwrap();
+
+        assert_eq!(Some("t0ken"), config.get_gitlab_token("foo.acme.tld"));
+        assert_eq!(Some("s3cret"), config.get_webhook_secret("foo.acme.tld"));
+
+        // The webhook secret is never sent in plain text to GitLab
+        assert_eq!(None, config.get_gitlab_token("bar.acme.tld"));
+    }
+
+    #[test]
+    pub fn test_find_site_name() {
+        let config = AlkaneConfig::load().unwrap();
+
+        assert_eq!(
+            Some("foo.acme.tld".to_string()),
+            config.find_site_name("acme.tld/foo")
+        );
+        assert_eq!(
+            Some("www.example.co.uk".to_string()),
+            config.find_site_name("example.co.uk/www")
+        );
+        assert_eq!(
+            Some("a.b.example.org".to_string()),
+            config.find_site_name("example.org/a.b")
+        );
+        assert_eq!(None, config.find_site_name("acme.tld"));
+
+        let yaml = r#"
+roots: {}
+site_directory_template: "%fqdn%"
+"#;
+        let config: AlkaneConfig = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(
+            Some("foo.acme.tld".to_string()),
+            config.find_site_name("foo.acme.tld")
+        );
+    }
+
+    #[test]
+    pub fn test_contains_domain_parts_variables() {
+        assert!(contains_domain_parts_variables("%domain%/%subdomain%"));
+        assert!(!contains_domain_parts_variables("%fqdn%"));
+        assert!(!contains_domain_parts_variables(""));
     }
 }
@@ -0,0 +1,214 @@
+//  -------------------------------------------------------------
+//  Alkane :: Database :: History
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+//  Project:        Nasqueron
+//  License:        BSD-2-Clause
+//  Description:    Journal of the deployments run for a site
+//  -------------------------------------------------------------
+
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::runner::{RecipeStatus, ResourceUsage};
+
+//  -------------------------------------------------------------
+//  Trigger source of a deployment
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+pub enum Trigger {
+    /// The deployment has been requested through the alkane command
+    #[serde(rename = "CLI")]
+    Cli,
+
+    /// The deployment has been requested through the HTTP API
+    #[serde(rename = "HTTP")]
+    Http,
+}
+
+impl Display for Trigger {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Trigger::Cli => write!(f, "CLI"),
+            Trigger::Http => write!(f, "HTTP"),
+        }
+    }
+}
+
+//  -------------------------------------------------------------
+//  Journal entry
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+/// Represents a deployment recorded in the site journal
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+pub struct HistoryEntry {
+    /// The run identifier, to find the output in the logs
+    #[serde(default)]
+    pub run_id: Option<u64>,
+
+    /// When the deployment started
+    pub timestamp: DateTime<Utc>,
+
+    /// The deployment action, "init" or "update"
+    pub action: String,
+
+    /// The status returned by the recipe
+    pub status: RecipeStatus,
+
+    /// How long the recipe ran, in milliseconds
+    pub duration: u64,
+
+    /// The SHA-256 digest of the build context, if any
+    pub context_digest: Option<String>,
+
+    /// Where the deployment has been requested from
+    pub trigger: Trigger,
+
+    /// The release made live, for sites using the releases layout
+    #[serde(default)]
+    pub release: Option<String>,
+
+    /// The resources used by the recipe, if it ran
+    #[serde(default)]
+    pub usage: Option<ResourceUsage>,
+}
+
+impl HistoryEntry {
+    pub fn new(
+        run_id: Option<u64>,
+        timestamp: DateTime<Utc>,
+        action: &str,
+        status: RecipeStatus,
+        duration: Duration,
+        context: Option<&str>,
+        trigger: Trigger,
+    ) -> Self {
+        Self {
+            run_id,
+            timestamp,
+            action: action.to_string(),
+            status,
+            duration: duration.as_millis() as u64,
+            context_digest: context.filter(|s| !s.is_empty()).map(compute_digest),
+            trigger,
+            release: None,
+            usage: None,
+        }
+    }
+}
+
+impl Display for HistoryEntry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.run_id {
+            Some(run_id) => write!(f, "#{}\t", run_id)?,
+            None => write!(f, "-\t")?,
+        }
+
+        write!(
+            f,
+            "{}\t{}\t{:?}\t{}ms\t{}",
+            self.timestamp.to_rfc3339(),
+            self.action,
+            self.status,
+            self.duration,
+            self.trigger,
+        )?;
+
+        if let Some(release) = &self.release {
+            write!(f, "\trelease {}", release)?;
+        }
+
+        if let Some(usage) = &self.usage {
+            write!(
+                f,
+                "\tcpu {}ms, rss {}KiB",
+                usage.get_cpu_time(),
+                usage.max_rss
+            )?;
+        }
+
+        if let Some(digest) = &self.context_digest {
+            write!(f, "\t{}", digest)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn compute_digest(context: &str) -> String {
+    format!("{:x}", Sha256::digest(context.as_bytes()))
+}
+
+//  -------------------------------------------------------------
+//  Tests
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_compute_digest() {
+        assert_eq!(64, compute_digest("CH3-CH3").len());
+        assert_eq!(
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            compute_digest("")
+        );
+    }
+
+    #[test]
+    pub fn test_new_ignores_empty_context() {
+        let entry = HistoryEntry::new(
+            Some(1),
+            Utc::now(),
+            "update",
+            RecipeStatus::Success,
+            Duration::from_millis(1500),
+            Some(""),
+            Trigger::Cli,
+        );
+
+        assert_eq!(None, entry.context_digest);
+        assert_eq!(1500, entry.duration);
+    }
+
+    #[test]
+    pub fn test_serialization_roundtrip() {
+        let entry = HistoryEntry::new(
+            Some(1),
+            Utc::now(),
+            "init",
+            RecipeStatus::Warning,
+            Duration::from_secs(2),
+            Some("CH3-CH3"),
+            Trigger::Http,
+        );
+
+        let line = serde_json::to_string(&entry).unwrap();
+        assert!(line.contains("\"trigger\":\"HTTP\""));
+
+        let parsed: HistoryEntry = serde_json::from_str(&line).unwrap();
+        assert_eq!(entry, parsed);
+    }
+
+    #[test]
+    pub fn test_usage() {
+        // Entries journaled before resources accounting have no usage
+        let line = r#"{"run_id":1,"timestamp":"2026-01-01T00:00:00Z","action":"update",
+            "status":"Success","duration":1500,"context_digest":null,"trigger":"CLI"}"#;
+        let mut entry: HistoryEntry = serde_json::from_str(line).unwrap();
+        assert_eq!(None, entry.usage);
+
+        entry.usage = Some(ResourceUsage {
+            wall_time: 1500,
+            user_time: 1000,
+            system_time: 200,
+            max_rss: 20480,
+        });
+        assert!(entry.to_string().ends_with("\tcpu 1200ms, rss 20480KiB"));
+    }
+}
@@ -0,0 +1,285 @@
+//  -------------------------------------------------------------
+//  Alkane :: Database :: Lock
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+//  Project:        Nasqueron
+//  License:        BSD-2-Clause
+//  Description:    Prevent concurrent recipe runs for a site
+//  -------------------------------------------------------------
+
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::{Error, ErrorKind, Write};
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::db::ensure_parent_directory_exists;
+
+/// How many times to read the lock file of another process,
+/// as it writes its information right after acquiring the lock
+const READ_ATTEMPTS: u32 = 20;
+
+/// How long to sleep between two attempts to read the lock file
+const READ_INTERVAL: Duration = Duration::from_millis(5);
+
+//  -------------------------------------------------------------
+//  Lock file content
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+/// Represents the holder of a site lock
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct LockInfo {
+    /// The process ID of the alkane process holding the lock
+    pub pid: u32,
+
+    /// When the lock has been acquired
+    pub started_at: DateTime<Utc>,
+
+    /// The deployment action run while holding the lock
+    pub action: String,
+}
+
+//  -------------------------------------------------------------
+//  Lock guard
+//
+//  The lock is an flock(2) exclusive lock on the lock file, so
+//  the kernel releases it if the process holding it dies. The
+//  file is removed while still locked when the guard is dropped.
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+#[derive(Debug)]
+pub struct SiteLock {
+    path: PathBuf,
+
+    /// The locked file, unlocked when closed
+    file: File,
+}
+
+impl Drop for SiteLock {
+    fn drop(&mut self) {
+        // The lock is only released when the file is closed, after
+        // the removal, so no other process can acquire it meanwhile.
+        if let Err(error) = fs::remove_file(&self.path) {
+            warn!("Can't remove lock {:?}: {:?}", &self.path, error);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum LockError {
+    /// Another process holds the lock, described by the lock file
+    /// if it could be read
+    Held(Option<LockInfo>),
+
+    /// The lock file can't be opened, locked or written
+    IO(Error),
+}
+
+/// Tries once to acquire the lock at the specified path
+pub fn try_acquire(path: PathBuf, action: &str) -> Result<SiteLock, LockError> {
+    ensure_parent_directory_exists(&path).map_err(LockError::IO)?;
+
+    loop {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(LockError::IO)?;
+
+        if let Err(error) = lock_file(&file) {
+            return match error.kind() {
+                // The holder could have released the lock meanwhile
+                ErrorKind::WouldBlock if !path.exists() => continue,
+                ErrorKind::WouldBlock => Err(LockError::Held(read_holder(&path))),
+                _ => Err(LockError::IO(error)),
+            };
+        }
+
+        // The previous holder removes the file before releasing the lock:
+        // if so, we've locked a removed file, and should lock the new one.
+        if !is_same_file(&file, &path) {
+            continue;
+        }
+
+        let mut lock = SiteLock { path, file };
+        write_lock_info(&mut lock.file, action).map_err(LockError::IO)?;
+
+        return Ok(lock);
+    }
+}
+
+fn lock_file(file: &File) -> std::io::Result<()> {
+    let result = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(Error::last_os_error())
+    }
+}
+
+/// Determines if the path still refers to the opened file
+fn is_same_file(file: &File, path: &Path) -> bool {
+    match (file.metadata(), fs::metadata(path)) {
+        (Ok(opened), Ok(current)) => opened.dev() == current.dev() && opened.ino() == current.ino(),
+        _ => false,
+    }
+}
+
+fn write_lock_info(file: &mut File, action: &str) -> std::io::Result<()> {
+    let info = LockInfo {
+        pid: process::id(),
+        started_at: Utc::now(),
+        action: action.to_string(),
+    };
+    let content = serde_json::to_string(&info)?;
+
+    // A lock file left by a dead process can still have content
+    file.set_len(0)?;
+    file.write_all(content.as_bytes())
+}
+
+/// Reads the information of the process holding the lock.
+///
+/// That process writes it just after acquiring the lock, so the file
+/// can still be empty: in such case, retries a few times.
+fn read_holder(path: &Path) -> Option<LockInfo> {
+    for attempt in 1..=READ_ATTEMPTS {
+        match read_lock_file(path) {
+            Ok(holder) => return Some(holder),
+            Err(error) if error.kind() == ErrorKind::NotFound => break,
+            Err(error) if attempt == READ_ATTEMPTS => {
+                warn!("Can't read lock {:?}: {:?}", path, error);
+            }
+            Err(_) => thread::sleep(READ_INTERVAL),
+        }
+    }
+
+    None
+}
+
+fn read_lock_file(path: &Path) -> Result<LockInfo, Error> {
+    let content = fs::read_to_string(path)?;
+
+    serde_json::from_str(&content).map_err(Error::from)
+}
+
+//  -------------------------------------------------------------
+//  Tests
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Barrier};
+
+    use super::*;
+
+    fn get_test_lock_path(name: &str) -> PathBuf {
+        std::env::temp_dir()
+            .join(format!("alkane-test-lock-{}", process::id()))
+            .join(name)
+    }
+
+    #[test]
+    pub fn test_lock_is_exclusive() {
+        let path = get_test_lock_path("foo.acme.tld");
+
+        let lock = try_acquire(path.clone(), "update").expect("Lock should be acquired");
+
+        match try_acquire(path.clone(), "update") {
+            Err(LockError::Held(Some(holder))) => assert_eq!(process::id(), holder.pid),
+            other => panic!("Lock should be held, got {:?}", other),
+        }
+
+        drop(lock);
+        assert!(!path.exists());
+
+        try_acquire(path, "update").expect("Lock should be acquired once released");
+    }
+
+    #[test]
+    pub fn test_unreadable_lock_is_kept() {
+        let path = get_test_lock_path("baz.acme.tld");
+
+        let lock = try_acquire(path.clone(), "update").expect("Lock should be acquired");
+        fs::write(&path, "").unwrap();
+
+        match try_acquire(path.clone(), "update") {
+            Err(LockError::Held(None)) => {}
+            other => panic!("Lock should be held, got {:?}", other),
+        }
+        assert!(path.exists());
+
+        drop(lock);
+    }
+
+    #[test]
+    pub fn test_concurrent_acquirers() {
+        let path = get_test_lock_path("race.acme.tld");
+        let holders = Arc::new(AtomicUsize::new(0));
+        let acquisitions = Arc::new(AtomicUsize::new(0));
+        let barrier = Arc::new(Barrier::new(8));
+
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let path = path.clone();
+                let holders = holders.clone();
+                let acquisitions = acquisitions.clone();
+                let barrier = barrier.clone();
+
+                thread::spawn(move || {
+                    barrier.wait();
+
+                    for _ in 0..100 {
+                        if let Ok(lock) = try_acquire(path.clone(), "update") {
+                            assert_eq!(0, holders.fetch_add(1, Ordering::SeqCst));
+                            acquisitions.fetch_add(1, Ordering::SeqCst);
+                            thread::sleep(Duration::from_micros(100));
+                            holders.fetch_sub(1, Ordering::SeqCst);
+
+                            drop(lock);
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        for thread in threads {
+            thread
+                .join()
+                .expect("Two acquirers held the lock at the same time");
+        }
+
+        assert!(acquisitions.load(Ordering::SeqCst) > 0);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    pub fn test_stale_lock_is_removed() {
+        let path = get_test_lock_path("bar.acme.tld");
+        ensure_parent_directory_exists(&path).unwrap();
+
+        let stale = LockInfo {
+            pid: i32::MAX as u32,
+            started_at: Utc::now(),
+            action: "init".to_string(),
+        };
+        fs::write(&path, serde_json::to_string(&stale).unwrap()).unwrap();
+
+        let lock = try_acquire(path.clone(), "update").expect("Stale lock should be replaced");
+        let holder = read_lock_file(&path).unwrap();
+        assert_eq!(process::id(), holder.pid);
+
+        drop(lock);
+    }
+}
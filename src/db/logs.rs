@@ -0,0 +1,93 @@
+//  -------------------------------------------------------------
+//  Alkane :: Database :: Logs
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+//  Project:        Nasqueron
+//  License:        BSD-2-Clause
+//  Description:    Output captured during a recipe run
+//  -------------------------------------------------------------
+
+use std::fmt::{Display, Formatter};
+
+use serde::{Deserialize, Serialize};
+
+/// Represents the output of a recipe run, persisted in the database
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct RunLog {
+    /// The run identifier, incremented for each run of a site
+    pub run_id: u64,
+
+    /// The deployment action, "init" or "update"
+    pub action: String,
+
+    /// The content written by the recipe on the standard output
+    pub stdout: String,
+
+    /// The content written by the recipe on the standard error
+    pub stderr: String,
+}
+
+impl RunLog {
+    /// Gets a copy of the log keeping only the last lines of each channel
+    pub fn tail(&self, lines: usize) -> Self {
+        Self {
+            run_id: self.run_id,
+            action: self.action.clone(),
+            stdout: tail_lines(&self.stdout, lines),
+            stderr: tail_lines(&self.stderr, lines),
+        }
+    }
+}
+
+impl Display for RunLog {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Run #{} ({})", self.run_id, self.action)?;
+        writeln!(f, "--- stdout ---")?;
+        write_channel(f, &self.stdout)?;
+        writeln!(f, "--- stderr ---")?;
+        write_channel(f, &self.stderr)
+    }
+}
+
+fn write_channel(f: &mut Formatter<'_>, content: &str) -> std::fmt::Result {
+    if content.is_empty() || content.ends_with('\n') {
+        write!(f, "{}", content)
+    } else {
+        writeln!(f, "{}", content)
+    }
+}
+
+fn tail_lines(content: &str, lines: usize) -> String {
+    if lines == 0 {
+        return String::new();
+    }
+
+    let start = content
+        .trim_end_matches('\n')
+        .rmatch_indices('\n')
+        .nth(lines - 1)
+        .map(|(index, _)| index + 1)
+        .unwrap_or(0);
+
+    content[start..].to_string()
+}
+
+//  -------------------------------------------------------------
+//  Tests
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_tail_lines() {
+        let content = "one\ntwo\nthree\n";
+
+        assert_eq!("three\n", tail_lines(content, 1));
+        assert_eq!("two\nthree\n", tail_lines(content, 2));
+        assert_eq!(content, tail_lines(content, 3));
+        assert_eq!(content, tail_lines(content, 10));
+        assert_eq!("", tail_lines(content, 0));
+        assert_eq!("three", tail_lines("one\ntwo\nthree", 1));
+    }
+}
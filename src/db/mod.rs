@@ -0,0 +1,729 @@
+//  -------------------------------------------------------------
+//  Alkane :: Database
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+//  Project:        Nasqueron
+//  License:        BSD-2-Clause
+//  -------------------------------------------------------------
+
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::Error as IOError;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::warn;
+
+use crate::config::AlkaneConfig;
+use crate::db::history::HistoryEntry;
+use crate::db::lock::{LockError, SiteLock};
+use crate::db::logs::RunLog;
+use crate::services::site_name::is_valid_site_name;
+
+//  -------------------------------------------------------------
+//  Modules
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+pub mod history;
+pub mod lock;
+pub mod logs;
+
+/// How long to sleep between two attempts to acquire a site lock
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How many bytes to read at once from the end of a deployment journal
+const HISTORY_CHUNK_SIZE: u64 = 4096;
+
+/// How many webhook deliveries to remember against replay
+const DELIVERIES_CAPACITY: usize = 10_000;
+
+//  -------------------------------------------------------------
+//  Database stored on the filesystem under the db root
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+pub struct Database {
+    root: String,
+}
+
+impl Database {
+    pub fn new<S>(root: S) -> Self
+    where
+        S: AsRef<str>,
+    {
+        Self {
+            root: root.as_ref().to_string(),
+        }
+    }
+
+    pub fn from_config(config: &AlkaneConfig) -> Option<Self> {
+        config.get_root("db").map(Self::new)
+    }
+
+    pub fn get_root(&self) -> &str {
+        &self.root
+    }
+
+    pub fn is_initialized(&self, site_name: &str) -> bool {
+        self.get_initialized_path(site_name)
+            .map(|path| path.exists())
+            .unwrap_or(false)
+    }
+
+    pub fn set_initialized(&self, site_name: &str) -> bool {
+        let path = match self.get_initialized_path(site_name) {
+            Some(path) => path,
+            None => return false,
+        };
+
+        if !path.exists() {
+            match ensure_parent_directory_exists(&path) {
+                Ok(_) => match touch(&path) {
+                    Ok(_) => true,
+                    Err(error) => {
+                        warn!("Can't mark site {} as initialized: {:?}", site_name, error);
+
+                        false
+                    }
+                },
+                Err(error) => {
+                    warn!("Can't create parent directory for {:?}: {:?}", &path, error);
+
+                    false
+                }
+            }
+        } else {
+            true
+        }
+    }
+
+    fn get_initialized_path(&self, site_name: &str) -> Option<PathBuf> {
+        self.get_site_path("initialized", site_name)
+    }
+
+    /// Gets the path of the site entry in a directory of the database,
+    /// or None if the site name can't safely be used as a file name.
+    fn get_site_path(&self, directory: &str, site_name: &str) -> Option<PathBuf> {
+        if !is_valid_site_name(site_name) {
+            warn!(
+                "Refusing to access database for invalid site name {:?}",
+                site_name
+            );
+
+            return None;
+        }
+
+        Some(Path::new(&self.root).join(directory).join(site_name))
+    }
+
+    /// Lists the sites marked as initialized
+    pub fn get_initialized_sites(&self) -> Vec<String> {
+        list_file_names(&Path::new(&self.root).join("initialized"))
+    }
+
+    /// Appends an entry to the deployment journal of the site
+    pub fn append_history(&self, site_name: &str, entry: &HistoryEntry) -> bool {
+        let path = match self.get_history_path(site_name) {
+            Some(path) => path,
+            None => return false,
+        };
+
+        let result = ensure_parent_directory_exists(&path).and_then(|_| {
+            let line = serde_json::to_string(entry)?;
+            let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+
+            writeln!(file, "{}", line)
+        });
+
+        match result {
+            Ok(_) => true,
+            Err(error) => {
+                warn!("Can't write history for site {}: {:?}", site_name, error);
+
+                false
+            }
+        }
+    }
+
+    /// Reads the deployment journal of the site, oldest entry first
+    pub fn get_history(&self, site_name: &str) -> Vec<HistoryEntry> {
+        let path = match self.get_history_path(site_name) {
+            Some(path) if path.exists() => path,
+            _ => return Vec::new(),
+        };
+
+        let file = match fs::File::open(&path) {
+            Ok(file) => file,
+            Err(error) => {
+                warn!("Can't read history for site {}: {:?}", site_name, error);
+
+                return Vec::new();
+            }
+        };
+
+        BufReader::new(file)
+            .split(b'\n')
+            .map_while(Result::ok)
+            .filter_map(|line| parse_history_line(site_name, &line))
+            .collect()
+    }
+
+    /// Reads the last entry of the deployment journal of the site.
+    pub fn get_last_history_entry(&self, site_name: &str) -> Option<HistoryEntry> {
+        self.find_last_history_entry(site_name, |_| true)
+    }
+
+    /// Finds the last entry of the deployment journal of the site
+    /// matching the predicate.
+    ///
+    /// The journal is read backwards from its end, stopping at the first
+    /// match, so the cost doesn't grow with the number of deployments since.
+    pub fn find_last_history_entry<P>(&self, site_name: &str, predicate: P) -> Option<HistoryEntry>
+    where
+        P: Fn(&HistoryEntry) -> bool,
+    {
+        let path = match self.get_history_path(site_name) {
+            Some(path) if path.exists() => path,
+            _ => return None,
+        };
+
+        let read_tail = || -> Result<Option<HistoryEntry>, IOError> {
+            let mut file = fs::File::open(&path)?;
+            let mut position = file.metadata()?.len();
+
+            // The bytes read so far, the first line being incomplete until we reach the start
+            let mut tail = Vec::new();
+
+            while position > 0 {
+                let size = HISTORY_CHUNK_SIZE.min(position);
+                position -= size;
+
+                let mut chunk = vec![0; size as usize];
+                file.seek(SeekFrom::Start(position))?;
+                file.read_exact(&mut chunk)?;
+                chunk.extend_from_slice(&tail);
+                tail = chunk;
+
+                let start = if position == 0 {
+                    0
+                } else {
+                    match tail.iter().position(|byte| *byte == b'\n') {
+                        Some(index) => index + 1,
+                        None => continue,
+                    }
+                };
+
+                let entry = tail[start..]
+                    .split(|byte| *byte == b'\n')
+                    .rev()
+                    .filter_map(|line| parse_history_line(site_name, line))
+                    .find(|entry| predicate(entry));
+                if entry.is_some() {
+                    return Ok(entry);
+                }
+
+                // Only keep the incomplete line to complete it with the previous chunk
+                tail.truncate(start);
+            }
+
+            Ok(None)
+        };
+
+        read_tail()
+            .map_err(|error| warn!("Can't read history for site {}: {:?}", site_name, error))
+            .ok()
+            .flatten()
+    }
+
+    fn get_history_path(&self, site_name: &str) -> Option<PathBuf> {
+        self.get_site_path("history", site_name)
+    }
+
+    /// Acquires the deployment lock of the site, waiting up to `timeout`
+    /// if another process holds it. The lock is released on drop.
+    pub fn lock(
+        &self,
+        site_name: &str,
+        action: &str,
+        timeout: Duration,
+    ) -> Result<SiteLock, LockError> {
+        let path = self.get_site_path("locks", site_name).ok_or_else(|| {
+            LockError::IO(IOError::new(ErrorKind::InvalidInput, "Invalid site name"))
+        })?;
+        let start = Instant::now();
+
+        loop {
+            match lock::try_acquire(path.clone(), action) {
+                Err(LockError::Held(_)) if start.elapsed() < timeout => {
+                    thread::sleep(LOCK_POLL_INTERVAL);
+                }
+
+                result => return result,
+            }
+        }
+    }
+
+    /// Allocates a new run identifier for the site
+    pub fn allocate_run_id(&self, site_name: &str) -> Option<u64> {
+        let path = self.get_site_path("runs", site_name)?;
+
+        let last_run_id = if path.exists() {
+            fs::read_to_string(&path)
+                .ok()
+                .and_then(|content| content.trim().parse::<u64>().ok())
+                .unwrap_or(0)
+        } else {
+            0
+        };
+        let run_id = last_run_id + 1;
+
+        let result = ensure_parent_directory_exists(&path)
+            .and_then(|_| fs::write(&path, run_id.to_string()));
+
+        match result {
+            Ok(_) => Some(run_id),
+            Err(error) => {
+                warn!("Can't allocate run id for site {}: {:?}", site_name, error);
+
+                None
+            }
+        }
+    }
+
+    /// Writes the output of a run, then only keeps the last `retention` runs.
+    /// The log just written is always kept.
+    pub fn write_run_log(&self, site_name: &str, log: &RunLog, retention: usize) -> bool {
+        let path = match self.get_run_log_path(site_name, log.run_id) {
+            Some(path) => path,
+            None => return false,
+        };
+
+        let result = ensure_parent_directory_exists(&path).and_then(|_| {
+            let content = serde_json::to_string(log)?;
+
+            fs::write(&path, content)
+        });
+
+        if let Err(error) = result {
+            warn!("Can't write log for site {}: {:?}", site_name, error);
+
+            return false;
+        }
+
+        let retention = retention.max(1);
+        let run_ids = self.get_run_ids(site_name);
+        if run_ids.len() > retention {
+            for run_id in &run_ids[..run_ids.len() - retention] {
+                let path = match self.get_run_log_path(site_name, *run_id) {
+                    Some(path) => path,
+                    None => continue,
+                };
+
+                if let Err(error) = fs::remove_file(&path) {
+                    warn!("Can't rotate log {:?}: {:?}", &path, error);
+                }
+            }
+        }
+
+        true
+    }
+
+    /// Reads the output of a run
+    pub fn get_run_log(&self, site_name: &str, run_id: u64) -> Option<RunLog> {
+        let path = self.get_run_log_path(site_name, run_id)?;
+
+        if !path.exists() {
+            return None;
+        }
+
+        let content = fs::read_to_string(&path)
+            .map_err(|error| warn!("Can't read log {:?}: {:?}", &path, error))
+            .ok()?;
+
+        serde_json::from_str(&content)
+            .map_err(|error| warn!("Ignoring malformed log {:?}: {}", &path, error))
+            .ok()
+    }
+
+    /// Gets the identifiers of the runs with a log available, in ascending order
+    pub fn get_run_ids(&self, site_name: &str) -> Vec<u64> {
+        let directory = match self.get_site_path("logs", site_name) {
+            Some(directory) => directory,
+            None => return Vec::new(),
+        };
+
+        let mut run_ids: Vec<u64> = match fs::read_dir(directory) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok())
+                .filter_map(|entry| {
+                    let path = entry.path();
+
+                    if path.extension()? != "json" {
+                        return None;
+                    }
+
+                    path.file_stem()?.to_str()?.parse().ok()
+                })
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+
+        run_ids.sort();
+        run_ids
+    }
+
+    /// Records the keys identifying a webhook delivery, like its delivery ID
+    /// and signature, keeping only the last ones received.
+    ///
+    /// Returns false without recording anything if one of the keys is known,
+    /// as the delivery has then already been received.
+    pub fn remember_delivery(&self, keys: &[String]) -> Result<bool, IOError> {
+        let path = Path::new(&self.root).join("deliveries");
+
+        let mut deliveries: Vec<String> = if path.exists() {
+            fs::read_to_string(&path)?
+                .lines()
+                .filter(|line| !line.is_empty())
+                .map(String::from)
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        if keys.iter().any(|key| deliveries.contains(key)) {
+            return Ok(false);
+        }
+
+        deliveries.extend(keys.iter().cloned());
+        if deliveries.len() > DELIVERIES_CAPACITY {
+            deliveries.drain(..deliveries.len() - DELIVERIES_CAPACITY);
+        }
+
+        // Written aside then renamed, so a crash can't truncate the journal
+        let new_path = path.with_extension("new");
+        ensure_parent_directory_exists(&path)?;
+        fs::write(&new_path, deliveries.join("\n") + "\n")?;
+        fs::rename(&new_path, &path)?;
+
+        Ok(true)
+    }
+
+    /// Writes then deletes a probe file under the db root,
+    /// to ensure the database is writable
+    pub fn check_writable(&self) -> Result<(), IOError> {
+        let path = Path::new(&self.root).join(format!(".probe-{}", std::process::id()));
+
+        fs::write(&path, b"alkane")?;
+        fs::remove_file(&path)
+    }
+
+    /// Forgets a removed site: the initialized marker, the runs counter
+    /// and the recipes output. The journal is kept for audit purpose.
+    pub fn clear_site(&self, site_name: &str) -> Result<(), IOError> {
+        let invalid = || IOError::new(ErrorKind::InvalidInput, "Invalid site name");
+
+        for directory in ["initialized", "runs"] {
+            let path = self
+                .get_site_path(directory, site_name)
+                .ok_or_else(invalid)?;
+
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+        }
+
+        let logs = self.get_site_path("logs", site_name).ok_or_else(invalid)?;
+        if logs.exists() {
+            fs::remove_dir_all(logs)?;
+        }
+
+        Ok(())
+    }
+
+    fn get_run_log_path(&self, site_name: &str, run_id: u64) -> Option<PathBuf> {
+        self.get_site_path("logs", site_name)
+            .map(|directory| directory.join(format!("{}.json", run_id)))
+    }
+}
+
+/// Parses a line of a deployment journal, ignoring blank or malformed lines
+fn parse_history_line(site_name: &str, line: &[u8]) -> Option<HistoryEntry> {
+    if line.trim_ascii().is_empty() {
+        return None;
+    }
+
+    match serde_json::from_slice(line) {
+        Ok(entry) => Some(entry),
+        Err(error) => {
+            warn!(
+                "Ignoring malformed history entry for site {}: {}",
+                site_name, error
+            );
+
+            None
+        }
+    }
+}
+
+/// Creates an empty file, similar to the touch command
+/// Ignores existing files.
+fn touch(path: &PathBuf) -> Result<(), IOError> {
+    let mut options = OpenOptions::new();
+    options.create(true).write(true);
+
+    options.open(path).map(|_| ())
+}
+
+/// Lists the names of the entries of a directory, sorted
+pub fn list_file_names(directory: &Path) -> Vec<String> {
+    let mut names: Vec<String> = match fs::read_dir(directory) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| entry.file_name().to_str().map(String::from))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+
+    names.sort();
+    names
+}
+
+fn ensure_parent_directory_exists(path: &Path) -> Result<(), IOError> {
+    let parent = path
+        .parent()
+        .ok_or_else(|| IOError::new(ErrorKind::InvalidInput, "Invalid path"))?;
+
+    if !parent.exists() {
+        fs::create_dir_all(parent)?;
+    }
+
+    Ok(())
+}
+
+//  -------------------------------------------------------------
+//  Tests
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    pub fn test_touch() {
+        let path = Path::new("tmp-touch.empty");
+        assert!(
+            !path.exists(),
+            "Temporary file tmp-touch.empty shouldn't exist when test starts"
+        );
+
+        touch(&path.to_path_buf()).expect("File can't be created");
+        assert!(
+            path.exists(),
+            "Function touch returned Ok but temporary file does NOT exist."
+        );
+
+        fs::remove_file(path).expect("Can't remove file after having created it.")
+    }
+
+    #[test]
+    pub fn test_history() {
+        use chrono::Utc;
+        use std::time::Duration;
+
+        use crate::db::history::Trigger;
+        use crate::runner::RecipeStatus;
+
+        let root = std::env::temp_dir().join(format!("alkane-test-history-{}", std::process::id()));
+        let db = Database::new(root.to_str().unwrap());
+        assert!(db.get_history("foo.acme.tld").is_empty());
+
+        for action in ["init", "update"] {
+            let entry = HistoryEntry::new(
+                None,
+                Utc::now(),
+                action,
+                RecipeStatus::Success,
+                Duration::from_millis(42),
+                None,
+                Trigger::Cli,
+            );
+            assert!(db.append_history("foo.acme.tld", &entry));
+        }
+
+        let history = db.get_history("foo.acme.tld");
+        assert_eq!(2, history.len());
+        assert_eq!("init", history[0].action);
+        assert_eq!("update", history[1].action);
+        assert_eq!(
+            Some("update"),
+            db.get_last_history_entry("foo.acme.tld")
+                .as_ref()
+                .map(|entry| entry.action.as_str())
+        );
+
+        fs::remove_dir_all(root).expect("Can't remove temporary database.")
+    }
+
+    #[test]
+    pub fn test_get_last_history_entry() {
+        use chrono::Utc;
+
+        use crate::db::history::Trigger;
+        use crate::runner::RecipeStatus;
+
+        let root =
+            std::env::temp_dir().join(format!("alkane-test-last-entry-{}", std::process::id()));
+        let db = Database::new(root.to_str().unwrap());
+        assert_eq!(None, db.get_last_history_entry("foo.acme.tld"));
+
+        // Spans several chunks
+        for run_id in 1..=100 {
+            let status = if run_id == 10 {
+                RecipeStatus::Success
+            } else {
+                RecipeStatus::Error
+            };
+            let entry = HistoryEntry::new(
+                Some(run_id),
+                Utc::now(),
+                "update",
+                status,
+                Duration::from_millis(42),
+                Some("CH3-CH3"),
+                Trigger::Cli,
+            );
+            assert!(db.append_history("foo.acme.tld", &entry));
+        }
+
+        let last = db.get_last_history_entry("foo.acme.tld").unwrap();
+        assert_eq!(Some(100), last.run_id);
+
+        let last_success = db
+            .find_last_history_entry("foo.acme.tld", |entry| {
+                entry.status == RecipeStatus::Success
+            })
+            .unwrap();
+        assert_eq!(Some(10), last_success.run_id);
+        assert_eq!(
+            None,
+            db.find_last_history_entry("foo.acme.tld", |entry| entry.action == "init")
+        );
+
+        // A truncated last line is ignored, as by get_history
+        let path = db.get_history_path("foo.acme.tld").unwrap();
+        let mut file = OpenOptions::new().append(true).open(path).unwrap();
+        write!(file, "{{\"run_id\":101,").unwrap();
+
+        let last = db.get_last_history_entry("foo.acme.tld").unwrap();
+        assert_eq!(Some(100), last.run_id);
+        assert_eq!(last, db.get_history("foo.acme.tld").pop().unwrap(),);
+
+        fs::remove_dir_all(root).expect("Can't remove temporary database.")
+    }
+
+    #[test]
+    pub fn test_clear_site() {
+        let root = std::env::temp_dir().join(format!("alkane-test-clear-{}", std::process::id()));
+        let db = Database::new(root.to_str().unwrap());
+
+        assert!(db.set_initialized("foo.acme.tld"));
+        assert_eq!(Some(1), db.allocate_run_id("foo.acme.tld"));
+        let log = RunLog {
+            run_id: 1,
+            action: "update".to_string(),
+            stdout: String::new(),
+            stderr: String::new(),
+        };
+        assert!(db.write_run_log("foo.acme.tld", &log, 10));
+
+        db.clear_site("foo.acme.tld").unwrap();
+        assert!(!db.is_initialized("foo.acme.tld"));
+        assert!(db.get_run_ids("foo.acme.tld").is_empty());
+        assert_eq!(Some(1), db.allocate_run_id("foo.acme.tld"));
+
+        // Clearing a site without state is fine
+        db.clear_site("bar.acme.tld").unwrap();
+
+        fs::remove_dir_all(root).expect("Can't remove temporary database.")
+    }
+
+    #[test]
+    pub fn test_invalid_site_name() {
+        let root = std::env::temp_dir()
+            .join(format!("alkane-test-invalid-{}", std::process::id()))
+            .join("db");
+        let db = Database::new(root.to_str().unwrap());
+
+        for site_name in ["..", "../escape", "...acme.tld"] {
+            assert!(!db.set_initialized(site_name));
+            assert!(!db.is_initialized(site_name));
+            assert_eq!(None, db.allocate_run_id(site_name));
+            assert!(db.get_history(site_name).is_empty());
+            assert!(db.lock(site_name, "update", Duration::ZERO).is_err());
+            assert!(db.clear_site(site_name).is_err());
+        }
+
+        assert!(!root.parent().unwrap().join("escape").exists());
+        assert!(!root.exists());
+    }
+
+    #[test]
+    pub fn test_remember_delivery() {
+        let root =
+            std::env::temp_dir().join(format!("alkane-test-deliveries-{}", std::process::id()));
+        let db = Database::new(root.to_str().unwrap());
+
+        let keys = vec!["github:1234".to_string(), "sha256=abcd".to_string()];
+        assert!(db.remember_delivery(&keys).unwrap());
+        assert!(!db.remember_delivery(&keys).unwrap());
+
+        // A new delivery ID doesn't allow to replay a known signature
+        let keys = vec!["github:5678".to_string(), "sha256=abcd".to_string()];
+        assert!(!db.remember_delivery(&keys).unwrap());
+
+        // Read back by a new instance, as after a restart
+        let db = Database::new(root.to_str().unwrap());
+        assert!(!db.remember_delivery(&["github:1234".to_string()]).unwrap());
+        assert!(db.remember_delivery(&["github:5678".to_string()]).unwrap());
+
+        fs::remove_dir_all(root).expect("Can't remove temporary database.")
+    }
+
+    #[test]
+    pub fn test_run_logs_rotation() {
+        let root = std::env::temp_dir().join(format!("alkane-test-logs-{}", std::process::id()));
+        let db = Database::new(root.to_str().unwrap());
+        assert!(db.get_run_ids("foo.acme.tld").is_empty());
+
+        for i in 1..=4 {
+            let run_id = db.allocate_run_id("foo.acme.tld").unwrap();
+            assert_eq!(i, run_id);
+
+            let log = RunLog {
+                run_id,
+                action: "update".to_string(),
+                stdout: format!("Run {}", run_id),
+                stderr: String::new(),
+            };
+            assert!(db.write_run_log("foo.acme.tld", &log, 3));
+        }
+
+        assert_eq!(vec![2, 3, 4], db.get_run_ids("foo.acme.tld"));
+        assert_eq!(None, db.get_run_log("foo.acme.tld", 1));
+        assert_eq!("Run 4", db.get_run_log("foo.acme.tld", 4).unwrap().stdout);
+
+        // Without retention, the log of the last run is still available
+        let log = RunLog {
+            run_id: db.allocate_run_id("foo.acme.tld").unwrap(),
+            action: "update".to_string(),
+            stdout: "Run 5".to_string(),
+            stderr: String::new(),
+        };
+        assert!(db.write_run_log("foo.acme.tld", &log, 0));
+        assert_eq!(vec![5], db.get_run_ids("foo.acme.tld"));
+
+        fs::remove_dir_all(root).expect("Can't remove temporary database.")
+    }
+}
@@ -5,9 +5,169 @@
 //  License:        BSD-2-Clause
 //  -------------------------------------------------------------
 
+use std::collections::BTreeMap;
 use std::error::Error;
 use std::fmt::{Display, Formatter};
 
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::db::lock::LockInfo;
+use crate::db::logs::RunLog;
+use crate::runner::{ExecutionError, RecipeStatus, ResourceUsage};
+
+//  -------------------------------------------------------------
+//  Result of a deployment
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+/// Represents the outcome of a deployment action once the recipe ran
+#[derive(Clone, Debug, Serialize)]
+pub struct DeploymentResult {
+    /// The run identifier, to find the output in the logs
+    pub run_id: Option<u64>,
+
+    pub site_name: String,
+
+    /// The deployment action actually run, "init", "update" or "rollback"
+    pub action: String,
+
+    /// The status returned by the recipe
+    pub status: RecipeStatus,
+
+    /// The live release, for sites using the releases layout
+    pub release: Option<String>,
+
+    pub started_at: DateTime<Utc>,
+
+    pub finished_at: DateTime<Utc>,
+
+    /// How long the action ran, in milliseconds
+    pub duration: u64,
+
+    /// The exit code of the recipe, if it ran and exited by itself
+    pub exit_code: Option<i32>,
+
+    /// The signal which terminated the recipe, if any
+    pub signal: Option<i32>,
+
+    /// The resources used by the recipe, if it ran
+    pub usage: Option<ResourceUsage>,
+
+    /// The last lines of the recipe output, if requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<RunLog>,
+}
+
+impl DeploymentResult {
+    /// Creates the result of an action which started at the specified time
+    /// and just finished
+    pub fn new(
+        site_name: &str,
+        action: &str,
+        status: RecipeStatus,
+        started_at: DateTime<Utc>,
+    ) -> Self {
+        let finished_at = Utc::now();
+
+        Self {
+            run_id: None,
+            site_name: site_name.to_string(),
+            action: action.to_string(),
+            status,
+            release: None,
+            started_at,
+            finished_at,
+            duration: (finished_at - started_at).num_milliseconds().max(0) as u64,
+            exit_code: None,
+            signal: None,
+            usage: None,
+            output: None,
+        }
+    }
+}
+
+//  -------------------------------------------------------------
+//  Plan of a deployment, to know what would be done
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+/// Represents what a deployment action would do, without running it
+#[derive(Debug, Serialize)]
+pub struct DeploymentPlan {
+    /// The configuration file loaded
+    pub config_file: Option<String>,
+
+    pub site_name: String,
+
+    pub site_directory_template: String,
+
+    /// The site directory relative to the sites root
+    pub site_subdir: String,
+
+    /// The directory the recipe would run against, as ALKANE_SITE_PATH
+    pub site_path: String,
+
+    /// The deployment action requested, like "deploy"
+    pub action: String,
+
+    /// The deployment action which would run, "init" or "update" for deploy
+    pub resolved_action: String,
+
+    /// If the action is enabled for the site
+    pub action_enabled: bool,
+
+    pub recipe_path: String,
+    pub recipe_exists: bool,
+    pub recipe_executable: bool,
+
+    /// If a new release would be created, for sites using the releases layout
+    pub new_release: bool,
+
+    /// How long the recipe could run, in seconds
+    pub timeout: Option<u64>,
+
+    /// The user the recipe would run as
+    pub user: Option<String>,
+
+    /// The environment variables given to the recipe
+    pub environment: BTreeMap<String, String>,
+}
+
+impl Display for DeploymentPlan {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let or_none = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+
+        writeln!(
+            f,
+            "Configuration file:  {}",
+            or_none(self.config_file.clone())
+        )?;
+        writeln!(f, "Site:                {}", self.site_name)?;
+        writeln!(f, "Directory template:  {}", self.site_directory_template)?;
+        writeln!(f, "Site directory:      {}", self.site_subdir)?;
+        writeln!(f, "Site path:           {}", self.site_path)?;
+        writeln!(f, "Action:              {}", self.action)?;
+        writeln!(f, "Resolved action:     {}", self.resolved_action)?;
+        writeln!(f, "Action enabled:      {}", self.action_enabled)?;
+        writeln!(f, "Recipe:              {}", self.recipe_path)?;
+        writeln!(f, "Recipe exists:       {}", self.recipe_exists)?;
+        writeln!(f, "Recipe executable:   {}", self.recipe_executable)?;
+        writeln!(f, "New release:         {}", self.new_release)?;
+        writeln!(
+            f,
+            "Timeout:             {}",
+            or_none(self.timeout.map(|timeout| format!("{}s", timeout)))
+        )?;
+        writeln!(f, "User:                {}", or_none(self.user.clone()))?;
+        writeln!(f, "Environment:")?;
+
+        for (key, value) in &self.environment {
+            writeln!(f, "  {}={}", key, value)?;
+        }
+
+        Ok(())
+    }
+}
+
 //  -------------------------------------------------------------
 //  Errors during our own workflow deployment
 //  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
@@ -49,19 +209,159 @@ impl AlkaneDeployError {
     }
 }
 
+//  -------------------------------------------------------------
+//  Error when another deployment runs for the same site
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+#[derive(Debug)]
+pub struct LockHeldError {
+    /// The name of the site to deploy
+    pub site_name: String,
+
+    /// The deployment action requested
+    pub action: String,
+
+    /// The process currently deploying the site, if known
+    pub holder: Option<LockInfo>,
+}
+
+impl Display for LockHeldError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.holder {
+            Some(holder) => write!(
+                f,
+                "Can't run deployment action '{}' for site '{}': action '{}' is already running since {} (pid {})",
+                self.action,
+                self.site_name,
+                holder.action,
+                holder.started_at.to_rfc3339(),
+                holder.pid
+            ),
+            None => write!(
+                f,
+                "Can't run deployment action '{}' for site '{}': another deployment is already running",
+                self.action, self.site_name
+            ),
+        }
+    }
+}
+
+impl Error for LockHeldError {}
+
+//  -------------------------------------------------------------
+//  Error when the recipe can't run to completion
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+#[derive(Debug)]
+pub struct ExecutionFailedError {
+    /// The name of the site to deploy
+    pub site_name: String,
+
+    /// The deployment action run
+    pub action: String,
+
+    pub error: ExecutionError,
+
+    /// The result of the run, with the output captured before a signal
+    pub result: Box<DeploymentResult>,
+}
+
+impl Display for ExecutionFailedError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Can't run deployment action '{}' for site '{}': {}",
+            self.action, self.site_name, self.error
+        )
+    }
+}
+
+impl Error for ExecutionFailedError {}
+
 //  -------------------------------------------------------------
 //  Errors that can occur during a deployment
 //  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
 
 #[derive(Debug)]
 pub enum DeployError {
-    Alkane(AlkaneDeployError),
+    /// A root or a setting needed to deploy is missing or invalid in the configuration
+    ConfigMissing(AlkaneDeployError),
+
+    /// The site directory can't be resolved from the site name
+    SiteUnresolvable(AlkaneDeployError),
+
+    /// The user to run the recipes as can't be resolved
+    UserUnresolvable(AlkaneDeployError),
+
+    /// There is no recipe for the action
+    RecipeNotFound(AlkaneDeployError),
+
+    /// The recipe exists but hasn't the executable bit
+    RecipeNotExecutable(AlkaneDeployError),
+
+    /// The recipe process can't be spawned, or has been terminated by a signal
+    Execution(ExecutionFailedError),
+
+    /// The recipe has been killed as it ran longer than the recipe timeout,
+    /// with the result of the run to find the output captured until then
+    Timeout(AlkaneDeployError, Box<DeploymentResult>),
+
+    /// Another deployment is running for the same site
+    LockHeld(LockHeldError),
+
+    /// The action isn't enabled for the site
+    ActionDisabled(AlkaneDeployError),
+
+    /// The request can't be fulfilled as is, like an invalid action name
+    InvalidRequest(AlkaneDeployError),
+
+    /// An I/O error prevented to complete the deployment workflow
+    Internal(AlkaneDeployError),
+}
+
+impl DeployError {
+    /// Gets the result of the run, if the recipe has been started
+    pub fn get_result(&self) -> Option<&DeploymentResult> {
+        match self {
+            DeployError::Execution(error) => Some(&error.result),
+            DeployError::Timeout(_, result) => Some(result),
+            _ => None,
+        }
+    }
+
+    /// Gets a stable machine-readable code for the error
+    pub fn get_code(&self) -> &'static str {
+        match self {
+            DeployError::ConfigMissing(_) => "config_missing",
+            DeployError::SiteUnresolvable(_) => "site_unresolvable",
+            DeployError::UserUnresolvable(_) => "user_unresolvable",
+            DeployError::RecipeNotFound(_) => "recipe_not_found",
+            DeployError::RecipeNotExecutable(_) => "recipe_not_executable",
+            DeployError::Execution(error) => error.error.get_code(),
+            DeployError::Timeout(..) => "timeout",
+            DeployError::LockHeld(_) => "lock_held",
+            DeployError::ActionDisabled(_) => "action_disabled",
+            DeployError::InvalidRequest(_) => "invalid_request",
+            DeployError::Internal(_) => "internal",
+        }
+    }
 }
 
 impl Display for DeployError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
-            DeployError::Alkane(error) => error.fmt(f),
+            DeployError::LockHeld(error) => error.fmt(f),
+            DeployError::Execution(error) => error.fmt(f),
+
+            DeployError::ConfigMissing(error)
+            | DeployError::SiteUnresolvable(error)
+            | DeployError::UserUnresolvable(error)
+            | DeployError::RecipeNotFound(error)
+            | DeployError::RecipeNotExecutable(error)
+            | DeployError::Timeout(error, _)
+            | DeployError::ActionDisabled(error)
+            | DeployError::InvalidRequest(error)
+            | DeployError::Internal(error) => error.fmt(f),
         }
     }
 }
@@ -0,0 +1,353 @@
+//  -------------------------------------------------------------
+//  Alkane :: Health
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+//  Project:        Nasqueron
+//  License:        BSD-2-Clause
+//  Description:    Determine if Alkane can deploy sites
+//  -------------------------------------------------------------
+
+use std::ffi::CString;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::config::{AlkaneConfig, AlkaneConfigError};
+use crate::db::Database;
+use crate::services::tld::count_public_suffixes;
+
+//  -------------------------------------------------------------
+//  Health report
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+#[derive(Debug, Serialize)]
+pub struct HealthCheck {
+    /// What has been checked, like "root sites"
+    pub check: String,
+
+    pub healthy: bool,
+
+    pub message: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HealthReport {
+    /// If every check is healthy
+    pub healthy: bool,
+
+    pub checks: Vec<HealthCheck>,
+}
+
+impl HealthReport {
+    fn new(checks: Vec<HealthCheck>) -> Self {
+        Self {
+            healthy: checks.iter().all(|check| check.healthy),
+            checks,
+        }
+    }
+
+    /// Gets the exit code, inspired by the Nagios ones like the recipes
+    pub fn to_status_code(&self) -> i32 {
+        if self.healthy {
+            0
+        } else {
+            2
+        }
+    }
+}
+
+impl Display for HealthReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for check in &self.checks {
+            let status = if check.healthy { "OK" } else { "FAIL" };
+
+            writeln!(f, "[{:4}] {}: {}", status, check.check, check.message)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Collects the health checks
+#[derive(Default)]
+struct Checks(Vec<HealthCheck>);
+
+impl Checks {
+    fn add<S: AsRef<str>>(&mut self, check: &str, healthy: bool, message: S) {
+        self.0.push(HealthCheck {
+            check: check.to_string(),
+            healthy,
+            message: message.as_ref().to_string(),
+        });
+    }
+
+    fn ok<S: AsRef<str>>(&mut self, check: &str, message: S) {
+        self.add(check, true, message);
+    }
+
+    fn fail<S: AsRef<str>>(&mut self, check: &str, message: S) {
+        self.add(check, false, message);
+    }
+}
+
+//  -------------------------------------------------------------
+//  Checks
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+/// Checks Alkane can deploy sites: the configuration is loaded,
+/// the roots are usable, the database is writable, the public suffix
+/// list is loaded and there is enough disk space for the sites.
+pub fn check_health(config: Result<&AlkaneConfig, &AlkaneConfigError>) -> HealthReport {
+    let mut checks = Checks::default();
+
+    let config = match config {
+        Ok(config) => {
+            match config.get_path() {
+                Some(path) => checks.ok("config", format!("loaded from {}", path)),
+                None => checks.ok("config", "loaded"),
+            }
+
+            Some(config)
+        }
+
+        Err(error) => {
+            checks.fail("config", format!("can't load configuration: {}", error));
+
+            None
+        }
+    };
+
+    if let Some(config) = config {
+        check_roots(&mut checks, config);
+        check_database(&mut checks, config);
+    }
+
+    match count_public_suffixes() {
+        0 => checks.fail("public suffix list", "empty"),
+        count => checks.ok("public suffix list", format!("{} suffixes", count)),
+    }
+
+    if let Some(config) = config {
+        check_disk_space(&mut checks, config);
+    }
+
+    HealthReport::new(checks.0)
+}
+
+fn check_roots(checks: &mut Checks, config: &AlkaneConfig) {
+    // The db root is created on the first deployment if needed
+    let roots = [
+        ("sites", libc::R_OK | libc::W_OK | libc::X_OK, true),
+        ("recipes", libc::R_OK | libc::X_OK, true),
+        ("db", libc::R_OK | libc::W_OK | libc::X_OK, false),
+    ];
+
+    for (root, mode, is_required) in roots {
+        let check = format!("root {}", root);
+
+        let path = match config.get_root(root) {
+            Some(path) => path,
+            None => {
+                checks.fail(&check, "not configured");
+                continue;
+            }
+        };
+
+        if !Path::new(&path).is_dir() {
+            if is_required {
+                checks.fail(&check, format!("{} doesn't exist", path));
+            } else {
+                checks.ok(&check, format!("{} doesn't exist yet", path));
+            }
+            continue;
+        }
+
+        if !is_accessible(&path, mode) {
+            let access = if mode & libc::W_OK != 0 {
+                "readable and writable"
+            } else {
+                "readable"
+            };
+            checks.fail(&check, format!("{} isn't {}", path, access));
+            continue;
+        }
+
+        // Anyone could write a recipe Alkane would run
+        if root == "recipes" && is_world_writable(&path) {
+            checks.fail(&check, format!("{} is world-writable", path));
+            continue;
+        }
+
+        checks.ok(&check, path);
+    }
+}
+
+fn check_database(checks: &mut Checks, config: &AlkaneConfig) {
+    let db = match Database::from_config(config) {
+        Some(db) => db,
+        None => {
+            checks.fail("db", "db root not configured");
+            return;
+        }
+    };
+
+    let root = db.get_root();
+    if !Path::new(root).exists() {
+        // The db root is created with its parent directories when needed
+        let ancestor = Path::new(root)
+            .ancestors()
+            .find(|path| path.exists())
+            .and_then(|path| path.to_str());
+
+        match ancestor {
+            Some(ancestor) if is_accessible(ancestor, libc::W_OK | libc::X_OK) => {
+                checks.ok("db", format!("can be created under {}", ancestor))
+            }
+            Some(ancestor) => checks.fail("db", format!("{} isn't writable", ancestor)),
+            None => checks.fail("db", format!("{} can't be created", root)),
+        }
+        return;
+    }
+
+    match db.check_writable() {
+        Ok(_) => checks.ok("db", "writable"),
+        Err(error) => checks.fail("db", format!("not writable: {}", error)),
+    }
+}
+
+fn check_disk_space(checks: &mut Checks, config: &AlkaneConfig) {
+    let Some(root) = config.get_root("sites") else {
+        return;
+    };
+
+    let min_free_space = config.get_min_free_space();
+
+    match get_free_space(&root) {
+        Ok(free_space) if free_space >= min_free_space => {
+            checks.ok("disk space", format_free_space(free_space));
+        }
+
+        Ok(free_space) => checks.fail(
+            "disk space",
+            format!(
+                "{}, below {} MiB",
+                format_free_space(free_space),
+                min_free_space / 1024 / 1024
+            ),
+        ),
+
+        Err(error) => checks.fail("disk space", format!("can't stat {}: {}", root, error)),
+    }
+}
+
+//  -------------------------------------------------------------
+//  Helper methods
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+/// Determines if the Alkane process can access the path with this mode,
+/// a combination of R_OK, W_OK and X_OK
+fn is_accessible(path: &str, mode: libc::c_int) -> bool {
+    match CString::new(path) {
+        Ok(path) => unsafe { libc::access(path.as_ptr(), mode) == 0 },
+        Err(_) => false,
+    }
+}
+
+fn is_world_writable(path: &str) -> bool {
+    fs::metadata(path)
+        .map(|metadata| metadata.permissions().mode() & 0o002 != 0)
+        .unwrap_or(false)
+}
+
+/// Gets the space available to unprivileged users on the filesystem
+/// of the path, in bytes
+fn get_free_space(path: &str) -> io::Result<u64> {
+    let path = CString::new(path).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+fn format_free_space(free_space: u64) -> String {
+    format!("{} MiB free", free_space / 1024 / 1024)
+}
+
+//  -------------------------------------------------------------
+//  Tests
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_check_health() {
+        let config = AlkaneConfig::load().unwrap();
+
+        let report = check_health(Ok(&config));
+        let checks: Vec<_> = report
+            .checks
+            .iter()
+            .map(|check| check.check.as_str())
+            .collect();
+        assert_eq!(
+            vec![
+                "config",
+                "root sites",
+                "root recipes",
+                "root db",
+                "db",
+                "public suffix list",
+                "disk space",
+            ],
+            checks
+        );
+
+        for check in &report.checks {
+            if check.check != "disk space" {
+                assert!(check.healthy, "{}: {}", check.check, check.message);
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_check_health_missing_roots() {
+        let yaml = r#"
+roots:
+  sites: /nonexistent/wwwroot
+  recipes: /nonexistent/recipes
+  db: /nonexistent/db
+site_directory_template: "%fqdn%"
+"#;
+        let config: AlkaneConfig = serde_yaml::from_str(yaml).unwrap();
+
+        let report = check_health(Ok(&config));
+        assert!(!report.healthy);
+        assert_eq!(2, report.to_status_code());
+
+        let unhealthy: Vec<_> = report
+            .checks
+            .iter()
+            .filter(|check| !check.healthy && check.check != "db")
+            .map(|check| check.check.as_str())
+            .collect();
+        assert_eq!(vec!["root sites", "root recipes", "disk space"], unhealthy);
+    }
+
+    #[test]
+    pub fn test_check_health_without_config() {
+        let report = check_health(Err(&AlkaneConfigError::FileNotFound));
+
+        assert!(!report.healthy);
+        assert_eq!(2, report.checks.len());
+        assert_eq!("config", report.checks[0].check);
+        assert!(report.checks[1].healthy);
+    }
+}
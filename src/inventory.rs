@@ -0,0 +1,218 @@
+//  -------------------------------------------------------------
+//  Alkane :: Inventory
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+//  Project:        Nasqueron
+//  License:        BSD-2-Clause
+//  Description:    Enumerate the sites known by Alkane
+//  -------------------------------------------------------------
+
+use std::collections::BTreeSet;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::config::AlkaneConfig;
+use crate::db::history::HistoryEntry;
+use crate::db::{list_file_names, Database};
+use crate::runner::store::RecipesStore;
+
+//  -------------------------------------------------------------
+//  Site information
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+#[derive(Debug, Serialize)]
+pub struct SiteInfo {
+    pub name: String,
+
+    /// If the site has been initialized by Alkane
+    pub initialized: bool,
+
+    /// The resolved site directory
+    pub path: Option<String>,
+
+    /// If the site directory exists
+    pub path_exists: bool,
+
+    /// The recipes available for the site, like "init" or "update"
+    pub recipes: Vec<String>,
+
+    /// The last entry of the deployments journal
+    pub last_deployment: Option<HistoryEntry>,
+}
+
+impl SiteInfo {
+    fn get_last_status(&self) -> String {
+        match &self.last_deployment {
+            Some(entry) => format!("{:?}", entry.status),
+            None => "-".to_string(),
+        }
+    }
+}
+
+//  -------------------------------------------------------------
+//  Inventory
+//
+//  A site is known if it appears in at least one of those places:
+//    - the initialized sites in the database
+//    - a recipes directory under the recipes root
+//    - a site directory under the sites root
+//    - a site section in the configuration
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+pub fn list_sites(config: &AlkaneConfig) -> Vec<SiteInfo> {
+    let db = Database::from_config(config);
+    let recipes = RecipesStore::from_config(config);
+
+    let mut site_names = BTreeSet::new();
+
+    if let Some(db) = &db {
+        site_names.extend(db.get_initialized_sites());
+    }
+
+    if let Some(recipes) = &recipes {
+        site_names.extend(recipes.get_site_names());
+    }
+
+    site_names.extend(find_sites_in_sites_root(config));
+    site_names.extend(config.get_configured_sites());
+
+    site_names
+        .into_iter()
+        .map(|site_name| {
+            let site = config.get_site(&site_name, None);
+            let path = site.as_ref().map(|site| site.path.clone());
+
+            SiteInfo {
+                initialized: db
+                    .as_ref()
+                    .map(|db| db.is_initialized(&site_name))
+                    .unwrap_or(false),
+                path_exists: path
+                    .as_ref()
+                    .map(|path| Path::new(path).exists())
+                    .unwrap_or(false),
+                recipes: match (&recipes, &site) {
+                    (Some(recipes), Some(site)) => recipes.get_recipes(site),
+                    _ => Vec::new(),
+                },
+                last_deployment: db
+                    .as_ref()
+                    .and_then(|db| db.get_last_history_entry(&site_name)),
+                name: site_name,
+                path,
+            }
+        })
+        .collect()
+}
+
+/// Finds the sites having a directory under the sites root
+fn find_sites_in_sites_root(config: &AlkaneConfig) -> Vec<String> {
+    let root = match config.get_root("sites") {
+        Some(root) => root,
+        None => return Vec::new(),
+    };
+
+    let mut subdirs = vec![String::new()];
+    for _ in 0..config.get_site_directory_depth() {
+        subdirs = subdirs
+            .into_iter()
+            .flat_map(|subdir| {
+                let directory = Path::new(&root).join(&subdir);
+
+                list_file_names(&directory)
+                    .into_iter()
+                    .filter(|name| !name.starts_with('.'))
+                    .filter(|name| directory.join(name).is_dir())
+                    .map(|name| {
+                        if subdir.is_empty() {
+                            name
+                        } else {
+                            format!("{}/{}", subdir, name)
+                        }
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+    }
+
+    subdirs
+        .iter()
+        .filter_map(|subdir| config.find_site_name(subdir))
+        .collect()
+}
+
+//  -------------------------------------------------------------
+//  Table output
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+pub struct SitesTable<'a>(pub &'a [SiteInfo]);
+
+impl Display for SitesTable<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let width = self
+            .0
+            .iter()
+            .map(|site| site.name.len())
+            .max()
+            .unwrap_or(0)
+            .max("SITE".len());
+
+        writeln!(
+            f,
+            "{:width$}  {:11}  {:8}  {:24}  PATH",
+            "SITE", "INITIALIZED", "STATUS", "RECIPES"
+        )?;
+
+        for site in self.0 {
+            let path = match (&site.path, site.path_exists) {
+                (Some(path), true) => path.clone(),
+                (Some(path), false) => format!("{} (missing)", path),
+                (None, _) => "-".to_string(),
+            };
+
+            writeln!(
+                f,
+                "{:width$}  {:11}  {:8}  {:24}  {}",
+                site.name,
+                if site.initialized { "yes" } else { "no" },
+                site.get_last_status(),
+                site.recipes.join(","),
+                path
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+//  -------------------------------------------------------------
+//  Tests
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_list_sites() {
+        let config = AlkaneConfig::load().unwrap();
+
+        let sites = list_sites(&config);
+        let site = sites
+            .iter()
+            .find(|site| site.name == "foo.acme.tld")
+            .expect("foo.acme.tld should be listed");
+
+        assert!(site.initialized);
+        assert!(site.path_exists);
+        assert_eq!(vec!["init", "update"], site.recipes);
+    }
+
+    #[test]
+    pub fn test_find_sites_in_sites_root() {
+        let config = AlkaneConfig::load().unwrap();
+
+        assert_eq!(vec!["foo.acme.tld"], find_sites_in_sites_root(&config));
+    }
+}
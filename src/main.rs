@@ -6,25 +6,39 @@
 //  Description:    Manage nginx and php-fpm Alkane PaaS
 //  -------------------------------------------------------------
 
+use std::fs::OpenOptions;
+use std::io::Write;
 use std::process::exit;
+use std::sync::Mutex;
 
 use clap::Parser;
+use log::warn;
 
 use crate::actions::*;
-use crate::command::{AlkaneCommand, ToStatusCode};
+use crate::check::check;
+use crate::command::{AlkaneCommand, OutputArgs, ToStatusCode};
 use crate::config::AlkaneConfig;
-use crate::deploy::DeployError;
-use crate::runner::RecipeStatus;
+use crate::db::history::Trigger;
+use crate::deploy::{DeployError, DeploymentPlan, DeploymentResult};
+use crate::health::check_health;
+use crate::inventory::{list_sites, SitesTable};
+use crate::removal::{FilesRemoval, RemoveOptions};
+use crate::runner::{Channel, OutputListener, RecipeStatus};
 
 //  -------------------------------------------------------------
 //  Modules
 //  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
 
 mod actions;
+mod check;
 mod command;
 mod config;
 mod db;
 mod deploy;
+mod health;
+mod inventory;
+mod releases;
+mod removal;
 mod runner;
 mod server;
 mod services;
@@ -38,15 +52,40 @@ async fn main() {
     env_logger::init();
 
     let command = AlkaneCommand::parse(); //  Will exit if argument is missing or --help/--version provided.
-    let config = match AlkaneConfig::load() {
+    let config = AlkaneConfig::load();
+
+    // The health report covers configuration errors too
+    if let AlkaneCommand::Health(args) = &command {
+        let report = check_health(config.as_ref());
+
+        if args.json {
+            match serde_json::to_string_pretty(&report) {
+                Ok(json) => println!("{}", json),
+                Err(error) => {
+                    eprintln!("Can't serialize health report: {}", error);
+                    exit(16);
+                }
+            }
+        } else {
+            print!("{}", report);
+        }
+
+        exit(report.to_status_code());
+    }
+
+    let config = match config {
         Ok(config) => config,
         Err(error) => {
-            eprintln!("Can't load configuration: {:?}", error);
+            eprintln!("Can't load configuration: {}", error);
             exit(4);
         }
     };
 
     match command {
+        AlkaneCommand::Health(_) => {
+            unreachable!("Health is handled before loading the configuration")
+        }
+
         AlkaneCommand::Server => {
             let result = serve(config).await;
 
@@ -54,20 +93,126 @@ async fn main() {
         }
 
         AlkaneCommand::Update(args) => {
-            let result = update(&args.site_name, None, &config);
+            if args.dry_run {
+                plan_exit(plan(&args.site_name, None, "update", &config));
+            }
+
+            let listener = get_output_listener(&args.output);
+            let result = update(&args.site_name, None, Trigger::Cli, listener, &config);
             deploy_exit(result);
         }
 
         AlkaneCommand::Init(args) => {
-            let result = initialize(&args.site_name, None, &config);
+            if args.dry_run {
+                plan_exit(plan(&args.site_name, None, "init", &config));
+            }
+
+            let listener = get_output_listener(&args.output);
+            let result = initialize(&args.site_name, None, Trigger::Cli, listener, &config);
             deploy_exit(result);
         }
 
         AlkaneCommand::Deploy(args) => {
-            let result = deploy(&args.site_name, None, &config);
+            if args.dry_run {
+                plan_exit(plan(&args.site_name, None, "deploy", &config));
+            }
+
+            let listener = get_output_listener(&args.output);
+            let result = deploy(&args.site_name, None, Trigger::Cli, listener, &config);
+            deploy_exit(result);
+        }
+
+        AlkaneCommand::Run(args) => {
+            if args.dry_run {
+                plan_exit(plan(&args.site_name, None, &args.action, &config));
+            }
+
+            let listener = get_output_listener(&args.output);
+            let result = run_custom_action(
+                &args.site_name,
+                &args.action,
+                None,
+                Trigger::Cli,
+                listener,
+                &config,
+            );
             deploy_exit(result);
         }
 
+        AlkaneCommand::Remove(args) => {
+            let files = if args.keep_files {
+                FilesRemoval::Keep
+            } else if args.delete {
+                FilesRemoval::Delete
+            } else {
+                FilesRemoval::Archive
+            };
+
+            let options = RemoveOptions {
+                confirm: args.confirm,
+                files,
+            };
+
+            let result = remove(&args.site_name, options, Trigger::Cli, &config);
+            deploy_exit(result);
+        }
+
+        AlkaneCommand::Rollback(args) => {
+            let result = rollback(&args.site_name, args.to, Trigger::Cli, &config);
+
+            if let Ok(result) = &result {
+                if let Some(release) = &result.release {
+                    println!("{}", release);
+                }
+            }
+
+            deploy_exit(result);
+        }
+
+        AlkaneCommand::List(args) => {
+            let sites = list_sites(&config);
+
+            if args.json {
+                match serde_json::to_string_pretty(&sites) {
+                    Ok(json) => println!("{}", json),
+                    Err(error) => {
+                        eprintln!("Can't serialize sites list: {}", error);
+                        exit(16);
+                    }
+                }
+            } else {
+                print!("{}", SitesTable(&sites));
+            }
+
+            exit(0);
+        }
+
+        AlkaneCommand::Check(args) => {
+            let site_names = match args.site_name {
+                Some(site_name) => vec![site_name],
+                None => list_sites(&config)
+                    .into_iter()
+                    .map(|site| site.name)
+                    .collect(),
+            };
+
+            let report = check(&config, &site_names);
+
+            if args.json {
+                match serde_json::to_string_pretty(&report) {
+                    Ok(json) => println!("{}", json),
+                    Err(error) => {
+                        eprintln!("Can't serialize check report: {}", error);
+                        exit(16);
+                    }
+                }
+            } else {
+                print!("{}", report);
+            }
+
+            exit(report.to_status_code());
+        }
+
         AlkaneCommand::IsPresent(args) => {
             let is_present = is_present(&args.site_name, &config);
 
@@ -82,12 +227,83 @@ async fn main() {
 
             exit(is_present.to_status_code());
         }
+
+        AlkaneCommand::History(args) => {
+            let history = get_history(&args.site_name, &config);
+
+            if args.json {
+                match serde_json::to_string_pretty(&history) {
+                    Ok(json) => println!("{}", json),
+                    Err(error) => {
+                        eprintln!("Can't serialize history: {}", error);
+                        exit(16);
+                    }
+                }
+            } else {
+                for entry in &history {
+                    println!("{}", entry);
+                }
+            }
+
+            exit(0);
+        }
+
+        AlkaneCommand::Logs(args) => {
+            let log = get_run_log(&args.site_name, args.run, &config);
+
+            match &log {
+                Some(log) => print!("{}", log),
+                None => eprintln!("No log available for this run."),
+            }
+
+            exit(log.to_status_code());
+        }
+    }
+}
+
+/// Prints the recipe output to the terminal while it runs,
+/// and appends it to the log file if requested.
+fn get_output_listener(args: &OutputArgs) -> Option<OutputListener> {
+    let log_file = args.log_file.as_ref().map(|path| {
+        match OpenOptions::new().create(true).append(true).open(path) {
+            Ok(file) => Mutex::new(file),
+            Err(error) => {
+                eprintln!("Can't open log file {}: {}", path, error);
+                exit(4);
+            }
+        }
+    });
+
+    if args.quiet && log_file.is_none() {
+        return None;
     }
+
+    let quiet = args.quiet;
+    let listener = OutputListener::new(move |line| {
+        if !quiet {
+            let _ = match line.channel {
+                Channel::Stdout => writeln!(std::io::stdout(), "{}", line.line),
+                Channel::Stderr => writeln!(std::io::stderr(), "{}", line.line),
+            };
+        }
+
+        // The file gets both channels, so each line is prefixed by its channel
+        if let Some(Ok(mut file)) = log_file.as_ref().map(Mutex::lock) {
+            if let Err(error) = writeln!(file, "[{}] {}", line.channel, line.line) {
+                warn!("Can't write recipe output to log file: {}", error);
+            }
+        }
+    });
+
+    Some(listener)
 }
 
-fn deploy_exit(result: Result<RecipeStatus, DeployError>) {
+fn plan_exit(result: Result<DeploymentPlan, DeployError>) {
     match result {
-        Ok(status) => exit(status.to_status_code()),
+        Ok(plan) => {
+            print!("{}", plan);
+            exit(0);
+        }
 
         Err(error) => {
             eprintln!("{}", error);
@@ -95,3 +311,20 @@ fn deploy_exit(result: Result<RecipeStatus, DeployError>) {
         }
     }
 }
+
+fn deploy_exit(result: Result<DeploymentResult, DeployError>) {
+    match result {
+        Ok(result) => exit(result.status.to_status_code()),
+
+        Err(error) => {
+            eprintln!("{}", error);
+
+            match error {
+                DeployError::LockHeld(_) => exit(17),
+                DeployError::Timeout(..) => exit(RecipeStatus::Timeout.to_status_code()),
+                DeployError::Execution(error) => exit(error.error.to_status_code()),
+                _ => exit(16),
+            }
+        }
+    }
+}
@@ -0,0 +1,232 @@
+//  -------------------------------------------------------------
+//  Alkane :: Releases
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+//  Project:        Nasqueron
+//  License:        BSD-2-Clause
+//  Description:    Release-based layout for a site directory
+//  -------------------------------------------------------------
+
+use std::fs;
+use std::io::{Error as IOError, ErrorKind};
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+use log::{info, warn};
+
+//  -------------------------------------------------------------
+//  Each deployment lands in its own release directory,
+//  and the live content is a symbolic link to one of them:
+//
+//      <site path>/releases/20231001T120000Z/
+//      <site path>/releases/20231002T093000Z/
+//      <site path>/current -> releases/20231002T093000Z
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+const RELEASES_DIRECTORY: &str = "releases";
+const CURRENT_LINK: &str = "current";
+
+pub struct Releases {
+    site_path: PathBuf,
+}
+
+impl Releases {
+    pub fn new<P>(site_path: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        Self {
+            site_path: site_path.as_ref().to_path_buf(),
+        }
+    }
+
+    fn get_releases_path(&self) -> PathBuf {
+        self.site_path.join(RELEASES_DIRECTORY)
+    }
+
+    pub fn get_release_path(&self, release: &str) -> PathBuf {
+        self.get_releases_path().join(release)
+    }
+
+    /// Gets the path of the live content, a symbolic link to the current release
+    pub fn get_current_path(&self) -> PathBuf {
+        self.site_path.join(CURRENT_LINK)
+    }
+
+    /// Lists the releases, oldest first
+    pub fn list(&self) -> Vec<String> {
+        let mut releases: Vec<String> = match fs::read_dir(self.get_releases_path()) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.path().is_dir())
+                .filter_map(|entry| entry.file_name().to_str().map(String::from))
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+
+        releases.sort_by(|a, b| get_sort_key(a).cmp(&get_sort_key(b)));
+        releases
+    }
+
+    /// Gets the release the live content points to
+    pub fn get_current(&self) -> Option<String> {
+        let target = fs::read_link(self.get_current_path()).ok()?;
+
+        target.file_name()?.to_str().map(String::from)
+    }
+
+    /// Gets the release deployed just before the current one
+    pub fn get_previous(&self) -> Option<String> {
+        let current = self.get_current()?;
+
+        self.list()
+            .into_iter()
+            .take_while(|release| *release != current)
+            .last()
+    }
+
+    /// Creates a new empty release directory, named after the current time
+    pub fn create(&self) -> Result<String, IOError> {
+        let timestamp = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+
+        let mut release = timestamp.clone();
+        let mut suffix = 1;
+        while self.get_release_path(&release).exists() {
+            release = format!("{}-{}", timestamp, suffix);
+            suffix += 1;
+        }
+
+        fs::create_dir_all(self.get_release_path(&release))?;
+
+        Ok(release)
+    }
+
+    /// Points the live content to the specified release.
+    ///
+    /// The link is replaced atomically: a new link is created aside,
+    /// then renamed over the current one.
+    pub fn activate(&self, release: &str) -> Result<(), IOError> {
+        // Only accept known releases, so a name like ../.. can't be used
+        if !self.list().iter().any(|candidate| candidate == release) {
+            return Err(IOError::new(
+                ErrorKind::NotFound,
+                format!("Release {} doesn't exist", release),
+            ));
+        }
+
+        let target = Path::new(RELEASES_DIRECTORY).join(release);
+        let temporary_link = self.site_path.join(format!(".{}.tmp", CURRENT_LINK));
+
+        if fs::symlink_metadata(&temporary_link).is_ok() {
+            fs::remove_file(&temporary_link)?;
+        }
+
+        symlink(&target, &temporary_link)?;
+        fs::rename(&temporary_link, self.get_current_path())?;
+
+        info!(
+            "Site {:?} now points to release {}",
+            &self.site_path, release
+        );
+        Ok(())
+    }
+
+    /// Removes a release directory
+    pub fn remove(&self, release: &str) -> Result<(), IOError> {
+        fs::remove_dir_all(self.get_release_path(release))
+    }
+
+    /// Removes the oldest releases to only keep `retention` of them.
+    /// The current release is always kept.
+    pub fn prune(&self, retention: usize) {
+        let current = self.get_current();
+        let releases = self.list();
+
+        if releases.len() <= retention {
+            return;
+        }
+
+        for release in &releases[..releases.len() - retention] {
+            if Some(release) == current.as_ref() {
+                continue;
+            }
+
+            if let Err(error) = self.remove(release) {
+                warn!("Can't remove release {}: {:?}", release, error);
+            }
+        }
+    }
+}
+
+/// Gets the key to sort releases in creation order: by timestamp, then by
+/// the suffix of releases created the same second, so -10 comes after -2.
+fn get_sort_key(release: &str) -> (&str, u64) {
+    release
+        .rsplit_once('-')
+        .and_then(|(timestamp, suffix)| Some((timestamp, suffix.parse().ok()?)))
+        .unwrap_or((release, 0))
+}
+
+//  -------------------------------------------------------------
+//  Tests
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_releases_lifecycle() {
+        let site_path =
+            std::env::temp_dir().join(format!("alkane-test-releases-{}", std::process::id()));
+        let releases = Releases::new(&site_path);
+        assert!(releases.list().is_empty());
+        assert_eq!(None, releases.get_current());
+
+        let first = releases.create().unwrap();
+        releases.activate(&first).unwrap();
+        let second = releases.create().unwrap();
+        releases.activate(&second).unwrap();
+        let third = releases.create().unwrap();
+        releases.activate(&third).unwrap();
+
+        assert_eq!(
+            vec![first.clone(), second.clone(), third.clone()],
+            releases.list()
+        );
+        assert_eq!(Some(third.clone()), releases.get_current());
+        assert_eq!(Some(second.clone()), releases.get_previous());
+
+        // Rollback to the first release, then prune: current one is kept
+        releases.activate(&first).unwrap();
+        releases.prune(1);
+        assert_eq!(vec![first.clone(), third.clone()], releases.list());
+        assert_eq!(None, releases.get_previous());
+
+        assert!(releases.activate("notexisting").is_err());
+        assert!(releases.activate("..").is_err());
+
+        fs::remove_dir_all(site_path).expect("Can't remove temporary site directory.")
+    }
+
+    #[test]
+    pub fn test_list_releases_created_the_same_second() {
+        let site_path = std::env::temp_dir().join(format!(
+            "alkane-test-releases-suffix-{}",
+            std::process::id()
+        ));
+        let releases = Releases::new(&site_path);
+
+        let mut expected = vec!["20231001T120000Z".to_string()];
+        expected.extend((1..=11).map(|suffix| format!("20231001T120000Z-{}", suffix)));
+        expected.push("20231001T120001Z".to_string());
+
+        for release in expected.iter().rev() {
+            fs::create_dir_all(releases.get_release_path(release)).unwrap();
+        }
+
+        assert_eq!(expected, releases.list());
+
+        fs::remove_dir_all(site_path).expect("Can't remove temporary site directory.")
+    }
+}
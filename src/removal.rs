@@ -0,0 +1,234 @@
+//  -------------------------------------------------------------
+//  Alkane :: Removal
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+//  Project:        Nasqueron
+//  License:        BSD-2-Clause
+//  Description:    Decommission the files of a removed site
+//  -------------------------------------------------------------
+
+use std::fs;
+use std::io::Error as IOError;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+use log::info;
+use serde::Deserialize;
+
+//  -------------------------------------------------------------
+//  What to do with the site directory
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FilesRemoval {
+    /// Delete the site directory
+    Delete,
+
+    /// Move the site directory under the archives root
+    #[default]
+    Archive,
+
+    /// Leave the site directory untouched
+    Keep,
+}
+
+/// Represents how a site removal has been requested
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+pub struct RemoveOptions {
+    /// The removal must be explicitly confirmed
+    #[serde(default)]
+    pub confirm: bool,
+
+    #[serde(default)]
+    pub files: FilesRemoval,
+}
+
+/// Checks the site directory can be deleted or archived: it must be
+/// a directory strictly below the sites root, and can't contain any
+/// of the other roots, like the database or the archives.
+///
+/// Returns the canonical path of the site directory to remove.
+pub fn check_site_path(
+    site_path: &Path,
+    sites_root: &str,
+    protected_roots: &[String],
+) -> Result<PathBuf, String> {
+    let metadata = match fs::symlink_metadata(site_path) {
+        Ok(metadata) => metadata,
+        Err(error) if error.kind() == ErrorKind::NotFound => return Ok(site_path.to_path_buf()),
+        Err(error) => return Err(format!("Can't read site directory: {}", error)),
+    };
+
+    // A symbolic link would resolve to another directory, maybe another site
+    if !metadata.is_dir() {
+        return Err(format!("{:?} isn't a directory", site_path));
+    }
+
+    let canonicalize = |path: &Path| {
+        fs::canonicalize(path).map_err(|error| format!("Can't resolve {:?}: {}", path, error))
+    };
+    let path = canonicalize(site_path)?;
+    let sites_root = canonicalize(Path::new(sites_root))?;
+
+    if path == sites_root || !path.starts_with(&sites_root) {
+        return Err(format!("{:?} isn't below the sites root", path));
+    }
+
+    for root in protected_roots {
+        if let Ok(root) = fs::canonicalize(root) {
+            if root.starts_with(&path) {
+                return Err(format!("{:?} contains the root {:?}", path, root));
+            }
+        }
+    }
+
+    Ok(path)
+}
+
+/// Deletes or archives the site directory.
+///
+/// The site path must have been checked with `check_site_path`.
+/// Returns the path of the archive, if the directory has been archived.
+pub fn remove_site_files(
+    site_name: &str,
+    site_path: &Path,
+    mode: FilesRemoval,
+    archives_root: &str,
+) -> Result<Option<PathBuf>, IOError> {
+    if mode == FilesRemoval::Keep || fs::symlink_metadata(site_path).is_err() {
+        return Ok(None);
+    }
+
+    match mode {
+        FilesRemoval::Delete => {
+            fs::remove_dir_all(site_path)?;
+            info!("Site directory {:?} deleted", site_path);
+
+            Ok(None)
+        }
+
+        FilesRemoval::Archive => {
+            let timestamp = Utc::now().format("%Y%m%dT%H%M%SZ");
+            let archive = Path::new(archives_root).join(format!("{}-{}", site_name, timestamp));
+
+            if archive.exists() {
+                return Err(IOError::new(
+                    std::io::ErrorKind::AlreadyExists,
+                    format!("Archive {:?} already exists", archive),
+                ));
+            }
+
+            fs::create_dir_all(archives_root)?;
+            fs::rename(site_path, &archive)?;
+            info!("Site directory {:?} archived to {:?}", site_path, archive);
+
+            Ok(Some(archive))
+        }
+
+        FilesRemoval::Keep => Ok(None),
+    }
+}
+
+//  -------------------------------------------------------------
+//  Tests
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_check_site_path() {
+        let root = std::env::temp_dir().join(format!("alkane-test-check-{}", std::process::id()));
+        let sites_root = root.join("wwwroot");
+        let site_path = sites_root.join("acme.tld").join("foo");
+        let db_root = sites_root.join("acme.tld").join("db");
+        fs::create_dir_all(&site_path).unwrap();
+        fs::create_dir_all(&db_root).unwrap();
+
+        let sites_root = sites_root.to_str().unwrap();
+        let protected_roots = [db_root.to_str().unwrap().to_string()];
+
+        assert_eq!(
+            Ok(fs::canonicalize(&site_path).unwrap()),
+            check_site_path(&site_path, sites_root, &protected_roots)
+        );
+
+        // Nothing to remove
+        let missing = site_path.join("missing");
+        assert_eq!(
+            Ok(missing.clone()),
+            check_site_path(&missing, sites_root, &protected_roots)
+        );
+
+        for path in [
+            root.join("wwwroot"),
+            root.clone(),
+            site_path.join("..").join(".."),
+            root.join("wwwroot").join("acme.tld"),
+        ] {
+            assert!(
+                check_site_path(&path, sites_root, &protected_roots).is_err(),
+                "{:?} shouldn't be removable",
+                path
+            );
+        }
+
+        let link = root.join("wwwroot").join("bar");
+        std::os::unix::fs::symlink(&site_path, &link).unwrap();
+        assert!(check_site_path(&link, sites_root, &protected_roots).is_err());
+
+        fs::remove_dir_all(root).expect("Can't remove temporary directory.")
+    }
+
+    #[test]
+    pub fn test_remove_site_files() {
+        let root = std::env::temp_dir().join(format!("alkane-test-removal-{}", std::process::id()));
+        let site_path = root.join("wwwroot").join("foo");
+        let archives_root = root.join("archives");
+        let archives_root = archives_root.to_str().unwrap();
+
+        fs::create_dir_all(&site_path).unwrap();
+        let result = remove_site_files(
+            "foo.acme.tld",
+            &site_path,
+            FilesRemoval::Keep,
+            archives_root,
+        );
+        assert_eq!(None, result.unwrap());
+        assert!(site_path.exists());
+
+        let archive = remove_site_files(
+            "foo.acme.tld",
+            &site_path,
+            FilesRemoval::Archive,
+            archives_root,
+        )
+        .unwrap()
+        .expect("An archive should have been created");
+        assert!(!site_path.exists());
+        assert!(archive.exists());
+
+        fs::create_dir_all(&site_path).unwrap();
+        let result = remove_site_files(
+            "foo.acme.tld",
+            &site_path,
+            FilesRemoval::Delete,
+            archives_root,
+        );
+        assert_eq!(None, result.unwrap());
+        assert!(!site_path.exists());
+
+        // A missing directory is fine
+        let result = remove_site_files(
+            "foo.acme.tld",
+            &site_path,
+            FilesRemoval::Delete,
+            archives_root,
+        );
+        assert!(result.is_ok());
+
+        fs::remove_dir_all(root).expect("Can't remove temporary directory.")
+    }
+}
@@ -7,32 +7,49 @@
 //  -------------------------------------------------------------
 
 use std::ffi::OsStr;
-use std::fmt::{Debug, Display};
-use std::process::Command;
+use std::fmt::{Debug, Display, Formatter};
+use std::io;
+use std::io::{BufRead, BufReader, Read};
+use std::mem;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
 use log::{error, info, warn};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+
+use crate::runner::sandbox::Sandbox;
+use crate::runner::user::RunAs;
 
 //  -------------------------------------------------------------
 //  Modules
 //  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
 
+pub mod sandbox;
 pub mod site;
 pub mod store;
+pub mod user;
 
 //  -------------------------------------------------------------
 //  Exit status of a recipe.
 //
 //  The executable called to build the site should use
 //  those exit code inspired by the Nagios one.
+//
+//  Timeout isn't returned by the executable, but set by Alkane
+//  when the recipe has been killed as it ran for too long.
 //  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
 
-#[derive(Debug, Serialize, PartialEq)]
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
 pub enum RecipeStatus {
     Success,
     Warning,
     Error,
     Unknown,
+    Timeout,
 }
 
 impl RecipeStatus {
@@ -51,15 +68,261 @@ impl RecipeStatus {
             RecipeStatus::Warning => 1,
             RecipeStatus::Error => 2,
             RecipeStatus::Unknown => 3,
+            RecipeStatus::Timeout => 124,
+        }
+    }
+}
+
+//  -------------------------------------------------------------
+//  Output of a recipe run
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+#[derive(Debug)]
+pub struct RecipeOutput {
+    pub status: RecipeStatus,
+
+    /// The exit code of the process, if it exited by itself
+    pub exit_code: Option<i32>,
+
+    /// The resources used by the process, if it could be waited for
+    pub usage: Option<ResourceUsage>,
+
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// Represents the resources used by a recipe process,
+/// including the children processes it waited for.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct ResourceUsage {
+    /// The wall-clock time, in milliseconds
+    pub wall_time: u64,
+
+    /// The CPU time spent in user mode, in milliseconds
+    pub user_time: u64,
+
+    /// The CPU time spent in system mode, in milliseconds
+    pub system_time: u64,
+
+    /// The maximum resident set size, in KiB
+    pub max_rss: u64,
+}
+
+impl ResourceUsage {
+    fn new(rusage: &libc::rusage, wall_time: Duration) -> Self {
+        Self {
+            wall_time: wall_time.as_millis() as u64,
+            user_time: to_millis(&rusage.ru_utime),
+            system_time: to_millis(&rusage.ru_stime),
+            max_rss: rusage.ru_maxrss as u64,
+        }
+    }
+
+    /// Gets the CPU time spent in user and system modes, in milliseconds
+    pub fn get_cpu_time(&self) -> u64 {
+        self.user_time + self.system_time
+    }
+}
+
+fn to_millis(time: &libc::timeval) -> u64 {
+    time.tv_sec as u64 * 1000 + time.tv_usec as u64 / 1000
+}
+
+//  -------------------------------------------------------------
+//  Errors preventing a recipe to run to completion
+//
+//  Those errors aren't reported by the recipe itself, contrary to
+//  the recipe status: the recipe couldn't be executed at all,
+//  or has been stopped by a signal.
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+#[derive(Debug)]
+pub enum ExecutionError {
+    /// The executable, or the interpreter in its shebang line, doesn't exist
+    NotFound,
+
+    /// The executable can't be run by the recipe user
+    PermissionDenied,
+
+    /// The executable isn't a binary or a script with a shebang line
+    ExecFormat,
+
+    /// The process has been terminated by a signal, like SIGKILL from the OOM killer
+    Signal {
+        signal: i32,
+        output: Box<RecipeOutput>,
+    },
+
+    /// The process can't be spawned for another reason
+    Spawn(io::Error),
+}
+
+impl ExecutionError {
+    /// Gets a stable machine-readable code for the error
+    pub fn get_code(&self) -> &'static str {
+        match self {
+            ExecutionError::NotFound => "exec_not_found",
+            ExecutionError::PermissionDenied => "exec_permission_denied",
+            ExecutionError::ExecFormat => "exec_format_error",
+            ExecutionError::Signal { .. } => "signal",
+            ExecutionError::Spawn(_) => "spawn_failed",
+        }
+    }
+
+    /// Gets the exit code, following the shell conventions:
+    /// 127 for command not found, 126 if it can't be executed,
+    /// and 128 + signal number if killed by a signal.
+    pub fn to_status_code(&self) -> i32 {
+        match self {
+            ExecutionError::NotFound => 127,
+            ExecutionError::Signal { signal, .. } => 128 + signal,
+            _ => 126,
+        }
+    }
+
+    /// Gets the output captured before the process has been terminated
+    pub fn get_output(&self) -> Option<&RecipeOutput> {
+        match self {
+            ExecutionError::Signal { output, .. } => Some(output),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ExecutionError {
+    fn from(error: io::Error) -> Self {
+        if error.raw_os_error() == Some(libc::ENOEXEC) {
+            return ExecutionError::ExecFormat;
+        }
+
+        match error.kind() {
+            io::ErrorKind::NotFound => ExecutionError::NotFound,
+            io::ErrorKind::PermissionDenied => ExecutionError::PermissionDenied,
+            _ => ExecutionError::Spawn(error),
+        }
+    }
+}
+
+impl Display for ExecutionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExecutionError::NotFound => {
+                write!(f, "recipe executable or interpreter not found")
+            }
+            ExecutionError::PermissionDenied => {
+                write!(f, "permission denied to execute the recipe")
+            }
+            ExecutionError::ExecFormat => {
+                write!(f, "recipe isn't a valid executable, check its shebang line")
+            }
+            ExecutionError::Signal { signal, .. } => {
+                write!(f, "recipe terminated by signal {}", signal)
+            }
+            ExecutionError::Spawn(error) => write!(f, "can't spawn recipe: {}", error),
         }
     }
 }
 
 //  -------------------------------------------------------------
-//  Run an executable, returns the recipe status
+//  Live output of a recipe
+//
+//  While the full output is returned when the recipe exits,
+//  a listener can follow it line by line as it's written.
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Channel {
+    Stdout,
+    Stderr,
+}
+
+impl Display for Channel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Channel::Stdout => write!(f, "stdout"),
+            Channel::Stderr => write!(f, "stderr"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct OutputLine {
+    pub channel: Channel,
+
+    /// The line, without the trailing newline
+    pub line: String,
+}
+
+/// Receives each line of the recipe output as soon as it's written
+#[derive(Clone)]
+pub struct OutputListener(Arc<dyn Fn(OutputLine) + Send + Sync>);
+
+impl OutputListener {
+    pub fn new<F>(listener: F) -> Self
+    where
+        F: Fn(OutputLine) + Send + Sync + 'static,
+    {
+        Self(Arc::new(listener))
+    }
+
+    fn notify(&self, channel: Channel, line: &[u8]) {
+        let line = read_bytes(line);
+        let line = line.trim_end_matches('\n').trim_end_matches('\r');
+
+        (self.0)(OutputLine {
+            channel,
+            line: line.to_string(),
+        })
+    }
+}
+
+impl Debug for OutputListener {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "OutputListener")
+    }
+}
+
+//  -------------------------------------------------------------
+//  Options to run a recipe
 //  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
 
-pub fn run<E, I, S>(command: S, args: I, environment: E) -> RecipeStatus
+#[derive(Debug, Default)]
+pub struct RunOptions {
+    /// If set, the recipe process group is killed after this delay
+    pub timeout: Option<Duration>,
+
+    /// If set, the recipe runs as this user instead of the Alkane one
+    pub user: Option<RunAs>,
+
+    /// If set, the recipe runs with a restricted environment and resources
+    pub sandbox: Option<Sandbox>,
+
+    /// If set, receives the recipe output line by line while it runs
+    pub listener: Option<OutputListener>,
+}
+
+//  -------------------------------------------------------------
+//  Run an executable, returns the recipe status and output,
+//  or an error if the executable can't run to completion.
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+/// How long to wait after SIGTERM before sending SIGKILL
+const KILL_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// How often to check if the process exited
+const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long to wait for the output pipes to be closed once the recipe exited,
+/// as a process it detached, like a daemon, can keep them open.
+const OUTPUT_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+pub fn run<E, I, S>(
+    command: S,
+    args: I,
+    environment: E,
+    options: &RunOptions,
+) -> Result<RecipeOutput, ExecutionError>
 where
     E: IntoIterator<Item = (S, S)>,
     I: IntoIterator<Item = S> + Debug,
@@ -67,39 +330,474 @@ where
 {
     info!("Running command {} with args {:?}", command, args);
 
-    let result = Command::new(command).args(args).envs(environment).output();
+    // The recipe runs in its own process group, so on timeout,
+    // we can kill it with every process it spawned.
+    let mut command = Command::new(command);
+    if let Some(sandbox) = &options.sandbox {
+        sandbox.apply(&mut command);
+    }
 
-    match result {
-        Ok(process_output) => {
-            let stdout = read_bytes(&process_output.stdout);
-            let stderr = read_bytes(&process_output.stderr);
+    command
+        .args(args)
+        .envs(environment)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0);
 
-            if !stdout.is_empty() {
-                info!("Channel stdout: {}", stdout);
-            }
+    if let Some(user) = options.user {
+        // Safety: drop_privileges only calls async-signal-safe functions
+        unsafe {
+            command.pre_exec(move || user.drop_privileges());
+        }
+    }
 
-            if !stderr.is_empty() {
-                warn!("Channel stderr: {}", stderr);
-            }
+    let mut child = command.spawn().map_err(|error| {
+        error!("Process can't spawn: {:?}", error);
+
+        ExecutionError::from(error)
+    })?;
+
+    let listener = options.listener.clone();
+    let stdout = read_pipe(child.stdout.take(), Channel::Stdout, listener.clone());
+    let stderr = read_pipe(child.stderr.take(), Channel::Stderr, listener);
 
-            match process_output.status.code() {
+    let start = Instant::now();
+    let mut exit_code = None;
+    let mut signal = None;
+    let mut usage = None;
+    let status = match wait_with_timeout(&child, options.timeout) {
+        Ok(Some((exit_status, rusage))) => {
+            usage = Some(ResourceUsage::new(&rusage, start.elapsed()));
+
+            match exit_status.code() {
                 None => {
-                    warn!("Process hard stopped by signal.");
+                    signal = exit_status.signal();
+                    warn!("Process hard stopped by signal {:?}.", signal);
 
                     RecipeStatus::Unknown
                 }
-                Some(code) => RecipeStatus::from_status_code(code),
+                Some(code) => {
+                    exit_code = Some(code);
+
+                    RecipeStatus::from_status_code(code)
+                }
             }
         }
 
+        Ok(None) => {
+            warn!(
+                "Process timed out after {:?}, killing it.",
+                options.timeout.unwrap_or_default()
+            );
+            usage = kill_process_group(&child)
+                .map(|rusage| ResourceUsage::new(&rusage, start.elapsed()));
+
+            RecipeStatus::Timeout
+        }
+
         Err(error) => {
-            error!("Process can't spawn: {:?}", error);
+            error!("Can't wait for process: {:?}", error);
+            kill_process_group(&child);
 
-            RecipeStatus::Error
+            RecipeStatus::Unknown
         }
+    };
+
+    let deadline = Instant::now() + OUTPUT_GRACE_PERIOD;
+    let stdout = stdout.collect(deadline);
+    let stderr = stderr.collect(deadline);
+
+    if !stdout.is_empty() {
+        info!("Channel stdout: {}", stdout);
+    }
+
+    if !stderr.is_empty() {
+        warn!("Channel stderr: {}", stderr);
+    }
+
+    let output = RecipeOutput {
+        status,
+        exit_code,
+        usage,
+        stdout,
+        stderr,
+    };
+
+    match signal {
+        Some(signal) => Err(ExecutionError::Signal {
+            signal,
+            output: Box::new(output),
+        }),
+        None => Ok(output),
     }
 }
 
-fn read_bytes(bytes: &Vec<u8>) -> String {
+/// Reads a pipe in a separate thread, so the process can't block
+/// on a full pipe buffer while we wait for it.
+///
+/// The pipe is read line by line, so a listener can follow the output.
+fn read_pipe<R>(pipe: Option<R>, channel: Channel, listener: Option<OutputListener>) -> PipeReader
+where
+    R: Read + Send + 'static,
+{
+    let output = Arc::new(Mutex::new(PipeOutput::default()));
+    let shared = output.clone();
+
+    let handle = thread::spawn(move || {
+        let pipe = match pipe {
+            Some(pipe) => pipe,
+            None => return,
+        };
+
+        let mut reader = BufReader::new(pipe);
+        let mut line = Vec::new();
+
+        loop {
+            line.clear();
+
+            match reader.read_until(b'\n', &mut line) {
+                Ok(0) => break,
+                Ok(_) => {
+                    {
+                        let mut output = shared.lock().unwrap();
+                        if output.is_abandoned {
+                            break;
+                        }
+
+                        output.buffer.extend_from_slice(&line);
+                    }
+
+                    // Notified once the lock is released, so a slow listener
+                    // doesn't block the collection of the output
+                    if let Some(listener) = &listener {
+                        listener.notify(channel, &line);
+                    }
+                }
+                Err(error) => {
+                    warn!("Can't read process output: {:?}", error);
+                    break;
+                }
+            }
+        }
+    });
+
+    PipeReader { handle, output }
+}
+
+#[derive(Default)]
+struct PipeOutput {
+    buffer: Vec<u8>,
+
+    /// Set when the output has been collected before the pipe was closed
+    is_abandoned: bool,
+}
+
+/// Represents a pipe read in a separate thread
+struct PipeReader {
+    handle: JoinHandle<()>,
+    output: Arc<Mutex<PipeOutput>>,
+}
+
+impl PipeReader {
+    /// Gets the output read, waiting up to the deadline for the pipe to be closed.
+    ///
+    /// If it isn't, the thread is abandoned and stops at the next line it reads,
+    /// closing the pipe, so a detached process can't block the run forever.
+    fn collect(self, deadline: Instant) -> String {
+        while !self.handle.is_finished() && Instant::now() < deadline {
+            thread::sleep(WAIT_POLL_INTERVAL);
+        }
+
+        let mut output = self.output.lock().unwrap();
+        if self.handle.is_finished() {
+            if self.handle.join().is_err() {
+                warn!("Process output reader panicked.");
+            }
+        } else {
+            warn!("Process output still open after the recipe exited, ignoring the rest.");
+            output.is_abandoned = true;
+        }
+
+        read_bytes(&output.buffer)
+    }
+}
+
+/// Waits for the process to exit, returns None if the timeout is reached first.
+///
+/// The process is reaped with wait4, to get its resource usage.
+fn wait_with_timeout(
+    child: &Child,
+    timeout: Option<Duration>,
+) -> io::Result<Option<(ExitStatus, libc::rusage)>> {
+    let timeout = match timeout {
+        None => return wait4(child, 0),
+        Some(timeout) => timeout,
+    };
+
+    let start = Instant::now();
+    loop {
+        if let Some(exited) = wait4(child, libc::WNOHANG)? {
+            return Ok(Some(exited));
+        }
+
+        if start.elapsed() >= timeout {
+            return Ok(None);
+        }
+
+        thread::sleep(WAIT_POLL_INTERVAL);
+    }
+}
+
+/// Reaps the process if it exited, returns None if it's still running with WNOHANG
+fn wait4(child: &Child, options: libc::c_int) -> io::Result<Option<(ExitStatus, libc::rusage)>> {
+    let pid = child.id() as libc::pid_t;
+    let mut status = 0;
+    let mut rusage: libc::rusage = unsafe { mem::zeroed() };
+
+    loop {
+        match unsafe { libc::wait4(pid, &mut status, options, &mut rusage) } {
+            0 => return Ok(None),
+            -1 => {
+                let error = io::Error::last_os_error();
+                if error.kind() != io::ErrorKind::Interrupted {
+                    return Err(error);
+                }
+            }
+            _ => return Ok(Some((ExitStatus::from_raw(status), rusage))),
+        }
+    }
+}
+
+/// Sends SIGTERM to the process group, then SIGKILL if it's still alive
+/// after the grace period. Returns the resource usage of the killed process.
+fn kill_process_group(child: &Child) -> Option<libc::rusage> {
+    let pgid = child.id() as libc::pid_t;
+
+    unsafe {
+        libc::killpg(pgid, libc::SIGTERM);
+    }
+
+    if let Ok(Some((_, rusage))) = wait_with_timeout(child, Some(KILL_GRACE_PERIOD)) {
+        return Some(rusage);
+    }
+
+    warn!("Process group {} still alive, sending SIGKILL.", pgid);
+    unsafe {
+        libc::killpg(pgid, libc::SIGKILL);
+    }
+
+    match wait4(child, 0) {
+        Ok(exited) => exited.map(|(_, rusage)| rusage),
+        Err(error) => {
+            error!("Can't wait for killed process: {:?}", error);
+
+            None
+        }
+    }
+}
+
+fn read_bytes(bytes: &[u8]) -> String {
     String::from_utf8_lossy(bytes).to_string()
 }
+
+//  -------------------------------------------------------------
+//  Tests
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+
+    fn run_shell(script: &str, timeout: Option<Duration>) -> RecipeOutput {
+        let options = RunOptions {
+            timeout,
+            ..Default::default()
+        };
+
+        run("/bin/sh", vec!["-c", script], Vec::new(), &options).unwrap()
+    }
+
+    #[test]
+    pub fn test_run_captures_output() {
+        let output = run_shell("echo out; echo err >&2; exit 1", None);
+
+        assert_eq!(RecipeStatus::Warning, output.status);
+        assert_eq!(Some(1), output.exit_code);
+        assert_eq!("out\n", output.stdout);
+        assert_eq!("err\n", output.stderr);
+    }
+
+    #[test]
+    pub fn test_run_timeout() {
+        let start = Instant::now();
+        let output = run_shell("echo started; sleep 30", Some(Duration::from_millis(200)));
+
+        assert_eq!(RecipeStatus::Timeout, output.status);
+        assert_eq!(None, output.exit_code);
+        assert_eq!("started\n", output.stdout);
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    pub fn test_run_timeout_detached_process() {
+        // The daemon leaves the process group, so it survives the kill
+        // and keeps stdout open.
+        let start = Instant::now();
+        let output = run_shell(
+            "setsid sleep 1000 & echo $!; sleep 30",
+            Some(Duration::from_millis(200)),
+        );
+
+        assert_eq!(RecipeStatus::Timeout, output.status);
+        assert!(start.elapsed() < Duration::from_secs(20));
+
+        let pid: libc::pid_t = output.stdout.trim().parse().unwrap();
+        unsafe {
+            libc::kill(pid, libc::SIGKILL);
+        }
+    }
+
+    #[test]
+    pub fn test_run_spawn_failures() {
+        let directory =
+            std::env::temp_dir().join(format!("alkane-test-runner-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+
+        let not_executable = directory.join("not-executable");
+        fs::write(&not_executable, "#!/bin/sh\n").unwrap();
+        fs::set_permissions(&not_executable, fs::Permissions::from_mode(0o644)).unwrap();
+
+        let no_shebang = directory.join("no-shebang");
+        fs::write(&no_shebang, "echo CH3-CH3\n").unwrap();
+        fs::set_permissions(&no_shebang, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let run_file = |path: &str| run(path, Vec::new(), Vec::new(), &RunOptions::default());
+
+        let error = run_file("/nonexistent/recipe").unwrap_err();
+        assert_eq!("exec_not_found", error.get_code());
+        assert_eq!(127, error.to_status_code());
+
+        let error = run_file(not_executable.to_str().unwrap()).unwrap_err();
+        assert_eq!("exec_permission_denied", error.get_code());
+        assert_eq!(126, error.to_status_code());
+
+        let error = run_file(no_shebang.to_str().unwrap()).unwrap_err();
+        assert_eq!("exec_format_error", error.get_code());
+
+        fs::remove_dir_all(directory).expect("Can't remove temporary directory.")
+    }
+
+    #[test]
+    pub fn test_run_killed_by_signal() {
+        let options = RunOptions::default();
+        let error = run(
+            "/bin/sh",
+            vec!["-c", "echo started; kill -9 $$"],
+            Vec::new(),
+            &options,
+        )
+        .unwrap_err();
+
+        assert_eq!(137, error.to_status_code());
+        assert_eq!("started\n", error.get_output().unwrap().stdout);
+    }
+
+    #[test]
+    pub fn test_run_listener() {
+        let lines = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let received = lines.clone();
+
+        let options = RunOptions {
+            listener: Some(OutputListener::new(move |line| {
+                received.lock().unwrap().push(line);
+            })),
+            ..Default::default()
+        };
+
+        let output = run(
+            "/bin/sh",
+            vec!["-c", "echo one; echo two; echo oops >&2; printf three"],
+            Vec::new(),
+            &options,
+        )
+        .unwrap();
+        assert_eq!("one\ntwo\nthree", output.stdout);
+
+        let lines = lines.lock().unwrap();
+        let stdout: Vec<_> = lines
+            .iter()
+            .filter(|line| line.channel == Channel::Stdout)
+            .map(|line| line.line.as_str())
+            .collect();
+        assert_eq!(vec!["one", "two", "three"], stdout);
+
+        let expected = OutputLine {
+            channel: Channel::Stderr,
+            line: "oops".to_string(),
+        };
+        assert!(lines.contains(&expected));
+    }
+
+    #[test]
+    pub fn test_run_as_user() {
+        let user = RunAs {
+            uid: 4242,
+            gid: 4343,
+        };
+        let options = RunOptions {
+            user: Some(user),
+            ..Default::default()
+        };
+
+        let output = run("/bin/sh", vec!["-c", "id -u; id -G"], Vec::new(), &options);
+
+        if unsafe { libc::geteuid() } == 0 {
+            // Supplementary groups of the Alkane user are cleared
+            assert_eq!("4242\n4343\n", output.unwrap().stdout);
+        } else {
+            assert_eq!("exec_permission_denied", output.unwrap_err().get_code());
+        }
+    }
+
+    #[test]
+    pub fn test_run_sandboxed() {
+        let mut sandbox: Sandbox =
+            serde_yaml::from_str("{umask: \"027\", limits: {open_files: 64}}").unwrap();
+        sandbox.working_directory = Some("/".to_string());
+
+        let options = RunOptions {
+            sandbox: Some(sandbox),
+            ..Default::default()
+        };
+
+        let script = "echo $PATH; echo ${HOME:-none} $ALKANE_SITE_NAME; pwd; umask; ulimit -n";
+        let environment = vec![("ALKANE_SITE_NAME", "foo.acme.tld")];
+        let output = run("/bin/sh", vec!["-c", script], environment, &options).unwrap();
+
+        assert_eq!(
+            "/usr/local/bin:/usr/bin:/bin\nnone foo.acme.tld\n/\n0027\n64\n",
+            output.stdout
+        );
+    }
+
+    #[test]
+    pub fn test_run_resource_usage() {
+        let output = run_shell("i=0; while [ $i -lt 100000 ]; do i=$((i+1)); done", None);
+        let usage = output.usage.unwrap();
+
+        assert!(usage.get_cpu_time() > 0);
+        assert!(usage.max_rss > 0);
+
+        let output = run_shell("sleep 30", Some(Duration::from_millis(200)));
+        assert!(output.usage.unwrap().wall_time >= 200);
+    }
+
+    #[test]
+    pub fn test_timeout_status_code() {
+        assert_eq!(124, RecipeStatus::Timeout.to_status_code());
+    }
+}
@@ -0,0 +1,249 @@
+//  -------------------------------------------------------------
+//  Alkane :: Runner :: Sandbox
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+//  Project:        Nasqueron
+//  License:        BSD-2-Clause
+//  Description:    Restrict the environment and resources of a recipe
+//  -------------------------------------------------------------
+
+use std::env;
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::Command;
+
+use serde::{Deserialize, Deserializer};
+
+/// The PATH given to sandboxed recipes, if not configured
+const DEFAULT_PATH: &str = "/usr/local/bin:/usr/bin:/bin";
+
+/// The umask of sandboxed recipes if not configured: files created
+/// by the recipe are only readable by the recipe user.
+const DEFAULT_UMASK: libc::mode_t = 0o077;
+
+//  -------------------------------------------------------------
+//  Sandbox settings
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+/// Represents the restrictions applied to a recipe process
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Sandbox {
+    /// Variables of the Alkane environment to pass to the recipe, like LANG
+    #[serde(default)]
+    pub environment: Vec<String>,
+
+    /// The PATH of the recipe
+    #[serde(default = "default_path")]
+    pub path: String,
+
+    /// The file mode creation mask, in octal like "027"
+    #[serde(default = "default_umask", deserialize_with = "deserialize_umask")]
+    pub umask: libc::mode_t,
+
+    #[serde(default)]
+    pub limits: ResourceLimits,
+
+    /// The working directory of the recipe, the site directory
+    #[serde(skip)]
+    pub working_directory: Option<String>,
+}
+
+/// Represents the resources a recipe can use, unlimited if not set
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+pub struct ResourceLimits {
+    /// The CPU time, in seconds
+    pub cpu_time: Option<u64>,
+
+    /// The address space size, in MiB
+    pub memory: Option<u64>,
+
+    /// The number of open files
+    pub open_files: Option<u64>,
+
+    /// The number of processes of the recipe user
+    pub processes: Option<u64>,
+}
+
+fn default_path() -> String {
+    DEFAULT_PATH.to_string()
+}
+
+fn default_umask() -> libc::mode_t {
+    DEFAULT_UMASK
+}
+
+/// Deserializes the umask from octal digits, as a string like "027",
+/// or as an integer, as YAML reads an unquoted umask like 27 or 022.
+fn deserialize_umask<'de, D>(deserializer: D) -> Result<libc::mode_t, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Umask {
+        Digits(String),
+        Number(u64),
+    }
+
+    let umask = match Umask::deserialize(deserializer).map_err(|_| {
+        serde::de::Error::custom("invalid umask: expected octal digits, like \"027\"")
+    })? {
+        Umask::Digits(umask) => umask,
+        Umask::Number(umask) => umask.to_string(),
+    };
+
+    parse_umask(&umask).ok_or_else(|| {
+        serde::de::Error::custom(format!(
+            "invalid umask {}: expected octal digits, like \"027\"",
+            umask
+        ))
+    })
+}
+
+fn parse_umask(umask: &str) -> Option<libc::mode_t> {
+    libc::mode_t::from_str_radix(umask, 8)
+        .ok()
+        .filter(|&umask| umask <= 0o777)
+}
+
+//  -------------------------------------------------------------
+//  Apply the sandbox to a command
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+impl Sandbox {
+    /// Sets the working directory to the site directory,
+    /// or to its parent if the site isn't initialized yet.
+    pub fn with_site_path(mut self, site_path: &str) -> Self {
+        let path = Path::new(site_path);
+
+        let directory = if path.is_dir() {
+            Some(path)
+        } else {
+            path.parent()
+        };
+        self.working_directory = directory.map(|path| path.to_string_lossy().to_string());
+
+        self
+    }
+
+    /// Clears the environment, so the recipe only gets the allowed
+    /// variables, and restricts the process resources.
+    ///
+    /// The recipe variables are set on the command afterward.
+    pub fn apply(&self, command: &mut Command) {
+        command.env_clear();
+
+        for name in &self.environment {
+            if let Some(value) = env::var_os(name) {
+                command.env(name, value);
+            }
+        }
+        command.env("PATH", &self.path);
+
+        if let Some(directory) = &self.working_directory {
+            command.current_dir(directory);
+        }
+
+        let umask = self.umask;
+        let limits = self.limits;
+
+        // Safety: the closure only calls async-signal-safe functions
+        unsafe {
+            command.pre_exec(move || {
+                libc::umask(umask);
+                limits.apply()
+            });
+        }
+    }
+}
+
+impl ResourceLimits {
+    fn apply(&self) -> io::Result<()> {
+        let limits = [
+            (libc::RLIMIT_CPU, self.cpu_time),
+            (libc::RLIMIT_AS, self.memory.map(|mib| mib * 1024 * 1024)),
+            (libc::RLIMIT_NOFILE, self.open_files),
+            (libc::RLIMIT_NPROC, self.processes),
+        ];
+
+        for (resource, value) in limits {
+            if let Some(value) = value {
+                let limit = libc::rlimit {
+                    rlim_cur: value as libc::rlim_t,
+                    rlim_max: value as libc::rlim_t,
+                };
+
+                if unsafe { libc::setrlimit(resource, &limit) } != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Lists the limits set to 0, which would prevent any recipe to run
+    pub fn get_zero_limits(&self) -> Vec<&'static str> {
+        [
+            ("cpu_time", self.cpu_time),
+            ("memory", self.memory),
+            ("open_files", self.open_files),
+            ("processes", self.processes),
+        ]
+        .into_iter()
+        .filter(|(_, value)| *value == Some(0))
+        .map(|(name, _)| name)
+        .collect()
+    }
+}
+
+//  -------------------------------------------------------------
+//  Tests
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_parse_umask() {
+        assert_eq!(Some(0o027), parse_umask("027"));
+        assert_eq!(Some(0o077), parse_umask("77"));
+        assert_eq!(None, parse_umask("999"));
+        assert_eq!(None, parse_umask("1777"));
+    }
+
+    #[test]
+    pub fn test_deserialize() {
+        let sandbox: Sandbox = serde_yaml::from_str("umask: \"027\"").unwrap();
+        assert_eq!(0o027, sandbox.umask);
+        assert_eq!(DEFAULT_PATH, sandbox.path);
+        assert!(sandbox.environment.is_empty());
+        assert_eq!(ResourceLimits::default(), sandbox.limits);
+
+        let sandbox: Sandbox = serde_yaml::from_str("limits: {memory: 512}").unwrap();
+        assert_eq!(DEFAULT_UMASK, sandbox.umask);
+        assert_eq!(Some(512), sandbox.limits.memory);
+
+        assert!(serde_yaml::from_str::<Sandbox>("umask: \"999\"").is_err());
+    }
+
+    #[test]
+    pub fn test_deserialize_invalid_umask() {
+        for yaml in ["umask: 99", "umask: true"] {
+            let error = serde_yaml::from_str::<Sandbox>(yaml).unwrap_err();
+            assert!(error.to_string().contains("invalid umask"), "{}", error);
+        }
+    }
+
+    #[test]
+    pub fn test_with_site_path() {
+        let sandbox: Sandbox = serde_yaml::from_str("{}").unwrap();
+
+        let sandbox = sandbox.with_site_path("/");
+        assert_eq!(Some("/".to_string()), sandbox.working_directory);
+
+        let sandbox = sandbox.with_site_path("/nonexistent/site");
+        assert_eq!(Some("/nonexistent".to_string()), sandbox.working_directory);
+    }
+}
@@ -6,10 +6,34 @@
 //  Description:    Represent the website metadata
 //  -------------------------------------------------------------
 
+use std::collections::HashMap;
+
+#[derive(Default)]
 pub struct Site {
     pub name: String,
     pub path: String,
 
     /// The build context, any metadata relevant to the build
     pub context: Option<String>,
+
+    /// The source code revision to deploy, when known
+    pub revision: Option<Revision>,
+
+    /// The directory containing the site recipes, if not the default one
+    pub recipes_path: Option<String>,
+
+    /// Additional environment variables for the recipes
+    pub environment: HashMap<String, String>,
+}
+
+/// Represents a revision pushed to a Git repository
+#[derive(Clone, Debug, PartialEq)]
+pub struct Revision {
+    /// The repository full name, like "nasqueron/alkane"
+    pub repository: String,
+
+    pub branch: String,
+
+    /// The commit SHA
+    pub commit: String,
 }
@@ -6,12 +6,13 @@
 //  -------------------------------------------------------------
 
 use std::collections::HashMap;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use crate::config::AlkaneConfig;
+use crate::db::list_file_names;
 use crate::runner::run;
 use crate::runner::site::Site;
-use crate::runner::RecipeStatus;
+use crate::runner::{ExecutionError, RecipeOutput, RunOptions};
 
 pub struct RecipesStore {
     root: String,
@@ -31,26 +32,65 @@ impl RecipesStore {
         config.get_root("recipes").map(Self::new)
     }
 
-    fn get_recipe_path(&self, site_name: &str, action: &str) -> String {
-        Path::new(&self.root)
-            .join(site_name)
+    fn get_recipes_directory(&self, site: &Site) -> PathBuf {
+        match &site.recipes_path {
+            Some(path) => Path::new(path).to_path_buf(),
+            None => Path::new(&self.root).join(&site.name),
+        }
+    }
+
+    pub fn get_recipe_path(&self, site: &Site, action: &str) -> String {
+        self.get_recipes_directory(site)
             .join(action)
             .to_str()
             .expect("Can't read recipe path as UTF-8")
             .to_string()
     }
 
-    pub fn run_recipe(&self, site: &Site, action: &str) -> RecipeStatus {
-        let command = self.get_recipe_path(&site.name, action);
-        let environment = self.get_environment(&site);
+    /// Lists the sites having a recipes directory under the recipes root
+    pub fn get_site_names(&self) -> Vec<String> {
+        list_file_names(Path::new(&self.root))
+            .into_iter()
+            .filter(|name| Path::new(&self.root).join(name).is_dir())
+            .collect()
+    }
+
+    /// Lists the recipes available for a site
+    pub fn get_recipes(&self, site: &Site) -> Vec<String> {
+        let directory = self.get_recipes_directory(site);
+
+        list_file_names(&directory)
+            .into_iter()
+            .filter(|name| directory.join(name).is_file())
+            .collect()
+    }
+
+    pub fn has_recipe(&self, site: &Site, action: &str) -> bool {
+        Path::new(&self.get_recipe_path(site, action)).is_file()
+    }
+
+    pub fn run_recipe(
+        &self,
+        site: &Site,
+        action: &str,
+        options: &RunOptions,
+    ) -> Result<RecipeOutput, ExecutionError> {
+        let command = self.get_recipe_path(site, action);
+        let environment = self.get_environment(site);
 
-        run(command, Vec::new(), environment)
+        run(command, Vec::new(), environment, options)
     }
 
-    fn get_environment(&self, site: &Site) -> HashMap<String, String> {
-        let mut map = HashMap::new();
+    pub fn get_environment(&self, site: &Site) -> HashMap<String, String> {
+        // Site variables first, so they can't override ALKANE_* ones
+        let mut map = site.environment.clone();
 
+        let site_recipes_path = self.get_recipes_directory(site);
         map.insert("ALKANE_RECIPES_PATH".to_string(), self.root.clone());
+        map.insert(
+            "ALKANE_SITE_RECIPES_PATH".to_string(),
+            site_recipes_path.to_string_lossy().to_string(),
+        );
         map.insert("ALKANE_SITE_NAME".to_string(), site.name.clone());
         map.insert("ALKANE_SITE_PATH".to_string(), site.path.clone());
 
@@ -60,6 +100,12 @@ impl RecipesStore {
             }
         }
 
+        if let Some(revision) = &site.revision {
+            map.insert("ALKANE_REPOSITORY".to_string(), revision.repository.clone());
+            map.insert("ALKANE_BRANCH".to_string(), revision.branch.clone());
+            map.insert("ALKANE_COMMIT_SHA".to_string(), revision.commit.clone());
+        }
+
         map
     }
 }
@@ -73,6 +119,7 @@ mod tests {
     use std::path::MAIN_SEPARATOR_STR;
 
     use super::*;
+    use crate::runner::site::Revision;
 
     #[test]
     pub fn test_get_recipe_path() {
@@ -81,7 +128,28 @@ mod tests {
         let test_store_path = "tests/data/recipes".replace("/", MAIN_SEPARATOR_STR);
         let store = RecipesStore::new(&test_store_path);
 
-        assert_eq!(expected, store.get_recipe_path("foo.acme.tld", "update"));
+        let site = Site {
+            name: "foo.acme.tld".to_string(),
+            ..Default::default()
+        };
+
+        assert_eq!(expected, store.get_recipe_path(&site, "update"));
+    }
+
+    #[test]
+    pub fn test_get_recipe_path_with_site_recipes() {
+        let store = RecipesStore::new("/path/to/recipes");
+
+        let site = Site {
+            name: "foo.acme.tld".to_string(),
+            recipes_path: Some("/srv/foo/recipes".to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            "/srv/foo/recipes/update",
+            store.get_recipe_path(&site, "update")
+        );
     }
 
     #[test]
@@ -91,6 +159,10 @@ mod tests {
             "ALKANE_RECIPES_PATH".to_string(),
             "tests/data/recipes".replace("/", MAIN_SEPARATOR_STR),
         );
+        expected.insert(
+            "ALKANE_SITE_RECIPES_PATH".to_string(),
+            "tests/data/recipes/foo.acme.tld".replace("/", MAIN_SEPARATOR_STR),
+        );
         expected.insert("ALKANE_SITE_NAME".to_string(), "foo.acme.tld".to_string());
         expected.insert(
             "ALKANE_SITE_PATH".to_string(),
@@ -103,18 +175,34 @@ mod tests {
         let site = Site {
             name: "foo.acme.tld".to_string(),
             path: "tests/data/wwwroot/acme.tld/foo".replace("/", MAIN_SEPARATOR_STR),
-            context: None,
+            ..Default::default()
         };
 
         assert_eq!(expected, store.get_environment(&site));
     }
 
+    #[test]
+    pub fn test_get_environment_with_site_recipes() {
+        let site = Site {
+            name: "foo.acme.tld".to_string(),
+            path: "/path/to/site".to_string(),
+            recipes_path: Some("/srv/foo/recipes".to_string()),
+            ..Default::default()
+        };
+
+        let environment = RecipesStore::new("/path/to/recipes").get_environment(&site);
+
+        assert_eq!("/path/to/recipes", environment["ALKANE_RECIPES_PATH"]);
+        assert_eq!("/srv/foo/recipes", environment["ALKANE_SITE_RECIPES_PATH"]);
+    }
+
     #[test]
     pub fn test_get_environment_with_context() {
         let site = Site {
             name: "foo.acme.tld".to_string(),
             path: "/path/to/site".to_string(),
             context: Some("CH3-CH3".to_string()),
+            ..Default::default()
         };
 
         let environment = RecipesStore::new("/path/to/recipes").get_environment(&site);
@@ -122,4 +210,44 @@ mod tests {
         assert!(environment.contains_key("ALKANE_SITE_CONTEXT"));
         assert_eq!("CH3-CH3", environment["ALKANE_SITE_CONTEXT"])
     }
+
+    #[test]
+    pub fn test_get_environment_with_revision() {
+        let site = Site {
+            name: "foo.acme.tld".to_string(),
+            path: "/path/to/site".to_string(),
+            context: None,
+            revision: Some(Revision {
+                repository: "acme/foo".to_string(),
+                branch: "main".to_string(),
+                commit: "0123456789abcdef".to_string(),
+            }),
+            ..Default::default()
+        };
+
+        let environment = RecipesStore::new("/path/to/recipes").get_environment(&site);
+
+        assert_eq!("acme/foo", environment["ALKANE_REPOSITORY"]);
+        assert_eq!("main", environment["ALKANE_BRANCH"]);
+        assert_eq!("0123456789abcdef", environment["ALKANE_COMMIT_SHA"]);
+    }
+
+    #[test]
+    pub fn test_get_environment_with_site_variables() {
+        let mut variables = HashMap::new();
+        variables.insert("APP_ENV".to_string(), "production".to_string());
+        variables.insert("ALKANE_SITE_NAME".to_string(), "spoofed".to_string());
+
+        let site = Site {
+            name: "foo.acme.tld".to_string(),
+            path: "/path/to/site".to_string(),
+            environment: variables,
+            ..Default::default()
+        };
+
+        let environment = RecipesStore::new("/path/to/recipes").get_environment(&site);
+
+        assert_eq!("production", environment["APP_ENV"]);
+        assert_eq!("foo.acme.tld", environment["ALKANE_SITE_NAME"]);
+    }
 }
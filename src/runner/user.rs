@@ -0,0 +1,242 @@
+//  -------------------------------------------------------------
+//  Alkane :: Runner :: User
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+//  Project:        Nasqueron
+//  License:        BSD-2-Clause
+//  Description:    Resolve the user to run a recipe as
+//  -------------------------------------------------------------
+
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::io::ErrorKind;
+use std::mem::MaybeUninit;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::ptr;
+
+use crate::config::SiteUser;
+
+/// Represents the user and group a recipe process runs as
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RunAs {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl RunAs {
+    /// Resolves the user configured for a site.
+    ///
+    /// Before init, the site directory can't exist yet: the owner of
+    /// its parent directory, where the recipe creates it, is used instead.
+    ///
+    /// The site directory owner is never root: a directory created
+    /// by root isn't an indication the site recipes should run as root.
+    pub fn resolve(user: &SiteUser, site_path: &str) -> Result<Self, String> {
+        match user {
+            SiteUser::Name(name) => {
+                Self::from_user_name(name).ok_or_else(|| format!("Unknown user {}", name))
+            }
+
+            SiteUser::Id {
+                uid,
+                gid: Some(gid),
+            } => Ok(Self {
+                uid: *uid,
+                gid: *gid,
+            }),
+
+            SiteUser::Id { uid, gid: None } => {
+                Self::from_uid(*uid).ok_or_else(|| format!("Unknown uid {}, gid must be set", uid))
+            }
+
+            SiteUser::Owner => match Self::from_owner(site_path) {
+                Ok(owner) if owner.uid == 0 => Err("Site directory is owned by root".to_string()),
+                Ok(owner) => Ok(owner),
+
+                Err(error) if error.kind() == ErrorKind::NotFound => {
+                    Self::from_parent_owner(site_path)
+                }
+                Err(error) => Err(format!("Can't read site directory owner: {}", error)),
+            },
+        }
+    }
+
+    /// Resolves a user name from the system users database,
+    /// using its primary group.
+    pub fn from_user_name(name: &str) -> Option<Self> {
+        let name = CString::new(name).ok()?;
+
+        Self::from_passwd(|passwd, buffer, length, result| unsafe {
+            libc::getpwnam_r(name.as_ptr(), passwd, buffer, length, result)
+        })
+    }
+
+    /// Resolves a user ID from the system users database,
+    /// using its primary group.
+    pub fn from_uid(uid: u32) -> Option<Self> {
+        Self::from_passwd(|passwd, buffer, length, result| unsafe {
+            libc::getpwuid_r(uid, passwd, buffer, length, result)
+        })
+    }
+
+    /// Gets the user and group owning a file or a directory
+    pub fn from_owner(path: &str) -> io::Result<Self> {
+        let metadata = fs::metadata(path)?;
+
+        Ok(Self {
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+        })
+    }
+
+    fn from_parent_owner(site_path: &str) -> Result<Self, String> {
+        let parent = Path::new(site_path)
+            .parent()
+            .and_then(|parent| parent.to_str())
+            .ok_or_else(|| "Site directory doesn't exist and has no parent".to_string())?;
+
+        match Self::from_owner(parent) {
+            Ok(owner) if owner.uid == 0 => Err(
+                "Site directory doesn't exist and its parent is owned by root, create it before init"
+                    .to_string(),
+            ),
+            Ok(owner) => Ok(owner),
+            Err(error) => Err(format!("Can't read site parent directory owner: {}", error)),
+        }
+    }
+
+    fn from_passwd<F>(lookup: F) -> Option<Self>
+    where
+        F: FnOnce(
+            *mut libc::passwd,
+            *mut libc::c_char,
+            usize,
+            *mut *mut libc::passwd,
+        ) -> libc::c_int,
+    {
+        let mut passwd = MaybeUninit::<libc::passwd>::uninit();
+        let mut buffer = vec![0 as libc::c_char; 4096];
+        let mut result = ptr::null_mut();
+
+        let code = lookup(
+            passwd.as_mut_ptr(),
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        );
+
+        if code != 0 || result.is_null() {
+            return None;
+        }
+
+        let passwd = unsafe { passwd.assume_init() };
+
+        Some(Self {
+            uid: passwd.pw_uid,
+            gid: passwd.pw_gid,
+        })
+    }
+
+    /// Switches the current process to this user and group, and clears
+    /// the supplementary groups, so the recipe doesn't keep any group
+    /// of the Alkane user.
+    ///
+    /// This is called in the forked process before exec, so it only
+    /// uses async-signal-safe functions.
+    pub fn drop_privileges(&self) -> io::Result<()> {
+        unsafe {
+            if libc::geteuid() != 0 {
+                // Without privileges, we can only run as ourselves
+                if libc::geteuid() == self.uid && libc::getegid() == self.gid {
+                    return Ok(());
+                }
+
+                return Err(io::Error::from_raw_os_error(libc::EPERM));
+            }
+
+            if libc::setgroups(0, ptr::null()) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            if libc::setgid(self.gid) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            if libc::setuid(self.uid) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+//  -------------------------------------------------------------
+//  Tests
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_from_user_name() {
+        let root = RunAs::from_user_name("root").unwrap();
+        assert_eq!(0, root.uid);
+        assert_eq!(0, root.gid);
+
+        assert_eq!(None, RunAs::from_user_name("notexisting-alkane-user"));
+    }
+
+    #[test]
+    pub fn test_from_uid() {
+        assert_eq!(RunAs::from_user_name("root"), RunAs::from_uid(0));
+    }
+
+    #[test]
+    pub fn test_resolve() {
+        let user = SiteUser::Id {
+            uid: 4242,
+            gid: Some(4343),
+        };
+        assert_eq!(
+            Ok(RunAs {
+                uid: 4242,
+                gid: 4343
+            }),
+            RunAs::resolve(&user, "")
+        );
+
+        let user = SiteUser::Name("notexisting-alkane-user".to_string());
+        assert!(RunAs::resolve(&user, "").is_err());
+
+        assert_eq!(
+            Err("Site directory is owned by root".to_string()),
+            RunAs::resolve(&SiteUser::Owner, "/")
+        );
+    }
+
+    #[test]
+    pub fn test_resolve_owner_before_init() {
+        let parent = std::env::temp_dir().join(format!("alkane-test-user-{}", std::process::id()));
+        fs::create_dir_all(&parent).unwrap();
+        let site_path = parent.join("foo.acme.tld");
+        let site_path = site_path.to_str().unwrap();
+
+        let owner = RunAs::from_owner(parent.to_str().unwrap()).unwrap();
+        let expected = if owner.uid == 0 {
+            Err(
+                "Site directory doesn't exist and its parent is owned by root, create it before init"
+                    .to_string(),
+            )
+        } else {
+            Ok(owner)
+        };
+        assert_eq!(expected, RunAs::resolve(&SiteUser::Owner, site_path));
+
+        assert!(RunAs::resolve(&SiteUser::Owner, "/nonexistent/foo.acme.tld").is_err());
+
+        fs::remove_dir_all(parent).unwrap();
+    }
+}
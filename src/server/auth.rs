@@ -0,0 +1,384 @@
+//  -------------------------------------------------------------
+//  Alkane :: Server :: Authentication
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+//  Project:        Nasqueron
+//  License:        BSD-2-Clause
+//  Description:    Bearer tokens scoped to sites and actions
+//  -------------------------------------------------------------
+
+use std::collections::HashMap;
+
+use axum::extract::{MatchedPath, RawPathParams, Request, State};
+use axum::http::header::{AUTHORIZATION, WWW_AUTHENTICATE};
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::{Json, RequestExt};
+use glob::Pattern;
+use log::{info, warn};
+use sha2::{Digest, Sha256};
+
+use crate::config::TokenConfig;
+use crate::server::requests::ErrorReply;
+use crate::server::state::ServerState;
+
+//  -------------------------------------------------------------
+//  Authorization errors
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+#[derive(Debug, PartialEq)]
+pub enum AuthError {
+    /// No bearer token has been sent
+    MissingToken,
+
+    /// The token doesn't match any configured token
+    InvalidToken,
+
+    /// The token is valid, but not allowed for this site or action
+    Forbidden(String),
+}
+
+impl IntoResponse for AuthError {
+    fn into_response(self) -> Response {
+        match self {
+            AuthError::MissingToken | AuthError::InvalidToken => {
+                let message = match self {
+                    AuthError::MissingToken => "Bearer token required",
+                    _ => "Invalid bearer token",
+                };
+
+                (
+                    StatusCode::UNAUTHORIZED,
+                    [(WWW_AUTHENTICATE, "Bearer")],
+                    Json(ErrorReply::new("unauthorized", message)),
+                )
+                    .into_response()
+            }
+
+            AuthError::Forbidden(_) => (
+                StatusCode::FORBIDDEN,
+                Json(ErrorReply::new(
+                    "forbidden",
+                    "Token not allowed for this site or action",
+                )),
+            )
+                .into_response(),
+        }
+    }
+}
+
+//  -------------------------------------------------------------
+//  Authorization logic
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+/// Determines if the Authorization header value allows the action on the site.
+///
+/// Returns the name of the matching token, or None if no token is configured,
+/// as the HTTP API doesn't require authentication in that case.
+pub fn authorize(
+    tokens: &HashMap<String, TokenConfig>,
+    authorization: Option<&str>,
+    site_name: Option<&str>,
+    action: &str,
+) -> Result<Option<String>, AuthError> {
+    if tokens.is_empty() {
+        return Ok(None);
+    }
+
+    let token = authorization
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| token.trim())
+        .filter(|token| !token.is_empty())
+        .ok_or(AuthError::MissingToken)?;
+
+    let hash = hash_token(token);
+    let (name, config) = tokens
+        .iter()
+        .find(|(_, config)| config.hash.eq_ignore_ascii_case(&hash))
+        .ok_or(AuthError::InvalidToken)?;
+
+    // Routes without site, like /metrics or /sites, are only scoped by action
+    let is_action_allowed = matches_any(&config.actions, action);
+    let is_site_allowed = match site_name {
+        Some(site_name) => matches_any(&config.sites, site_name),
+        None => true,
+    };
+
+    if is_action_allowed && is_site_allowed {
+        Ok(Some(name.clone()))
+    } else {
+        Err(AuthError::Forbidden(name.clone()))
+    }
+}
+
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+fn matches_any(patterns: &[String], value: &str) -> bool {
+    patterns.iter().any(|pattern| match Pattern::new(pattern) {
+        Ok(pattern) => pattern.matches(value),
+        Err(error) => {
+            warn!("Invalid pattern '{}' in tokens scope: {}", pattern, error);
+
+            false
+        }
+    })
+}
+
+//  -------------------------------------------------------------
+//  Middleware
+//
+//  The action is the first segment of the route, e.g. "update"
+//  for /update/{site_name}, or "run:<action>" for custom actions
+//  at /run/{site_name}/{action}, so a recipe named like a route,
+//  e.g. history, isn't allowed by a token for this route.
+//  For jobs, the site is the job one.
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+fn get_action(route: &str, custom_action: Option<&str>) -> String {
+    match custom_action {
+        Some(action) => format!("run:{}", action),
+        None => route.to_string(),
+    }
+}
+
+pub async fn authenticate(
+    State(state): State<ServerState>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, AuthError> {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .and_then(|path| path.as_str().trim_start_matches('/').split('/').next())
+        .unwrap_or_default()
+        .to_string();
+
+    let parameters = request.extract_parts::<RawPathParams>().await.ok();
+    let parameter = |key: &str| {
+        parameters.as_ref().and_then(|parameters| {
+            parameters
+                .iter()
+                .find(|(name, _)| *name == key)
+                .map(|(_, value)| value.to_string())
+        })
+    };
+
+    let action = get_action(&route, parameter("action").as_deref());
+
+    let site_name = match parameter("site_name") {
+        Some(site_name) => Some(site_name),
+        None => parameter("job_id")
+            .and_then(|job_id| state.jobs.get(&job_id))
+            .map(|job| job.site_name),
+    };
+
+    let authorization = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok());
+
+    match authorize(
+        state.config.get_tokens(),
+        authorization,
+        site_name.as_deref(),
+        &action,
+    ) {
+        Ok(Some(token_name)) => {
+            info!("Request {} authorized with token {}", &action, token_name)
+        }
+        Ok(None) => {}
+        Err(error) => {
+            warn!(
+                "Request {} for site {:?} denied: {:?}",
+                &action, &site_name, &error
+            );
+
+            return Err(error);
+        }
+    }
+
+    Ok(next.run(request).await)
+}
+
+//  -------------------------------------------------------------
+//  Tests
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_tokens() -> HashMap<String, TokenConfig> {
+        let mut tokens = HashMap::new();
+
+        tokens.insert(
+            "jenkins".to_string(),
+            TokenConfig {
+                hash: hash_token("CH3-CH3"),
+                sites: vec!["*.acme.tld".to_string()],
+                actions: vec!["update".to_string(), "deploy".to_string()],
+            },
+        );
+
+        tokens.insert(
+            "admin".to_string(),
+            TokenConfig {
+                hash: hash_token("C2H6"),
+                sites: vec!["*".to_string()],
+                actions: vec!["*".to_string()],
+            },
+        );
+
+        tokens
+    }
+
+    #[test]
+    pub fn test_authorize_without_tokens() {
+        let tokens = HashMap::new();
+
+        assert_eq!(
+            Ok(None),
+            authorize(&tokens, None, Some("foo.acme.tld"), "init")
+        );
+    }
+
+    #[test]
+    pub fn test_authorize() {
+        let tokens = get_tokens();
+
+        assert_eq!(
+            Ok(Some("jenkins".to_string())),
+            authorize(
+                &tokens,
+                Some("Bearer CH3-CH3"),
+                Some("foo.acme.tld"),
+                "update"
+            )
+        );
+        assert_eq!(
+            Ok(Some("admin".to_string())),
+            authorize(&tokens, Some("Bearer C2H6"), None, "jobs")
+        );
+    }
+
+    #[test]
+    pub fn test_authorize_without_site() {
+        let mut tokens = get_tokens();
+        tokens.insert(
+            "prometheus".to_string(),
+            TokenConfig {
+                hash: hash_token("CH4"),
+                sites: vec!["*.acme.tld".to_string()],
+                actions: vec!["metrics".to_string()],
+            },
+        );
+
+        assert_eq!(
+            Ok(Some("prometheus".to_string())),
+            authorize(&tokens, Some("Bearer CH4"), None, "metrics")
+        );
+        assert_eq!(
+            Err(AuthError::Forbidden("prometheus".to_string())),
+            authorize(&tokens, Some("Bearer CH4"), None, "sites")
+        );
+        assert_eq!(
+            Err(AuthError::Forbidden("jenkins".to_string())),
+            authorize(&tokens, Some("Bearer CH3-CH3"), None, "metrics")
+        );
+    }
+
+    #[test]
+    pub fn test_authorize_custom_action() {
+        let mut tokens = get_tokens();
+        tokens.insert(
+            "reader".to_string(),
+            TokenConfig {
+                hash: hash_token("CH4"),
+                sites: vec!["*".to_string()],
+                actions: vec!["history".to_string(), "logs".to_string()],
+            },
+        );
+        tokens.insert(
+            "cron".to_string(),
+            TokenConfig {
+                hash: hash_token("C3H8"),
+                sites: vec!["*".to_string()],
+                actions: vec!["run:*".to_string()],
+            },
+        );
+
+        // A recipe named like a route isn't allowed by a token for the route
+        let action = get_action("run", Some("history"));
+        assert_eq!("run:history", action);
+        assert_eq!(
+            Err(AuthError::Forbidden("reader".to_string())),
+            authorize(&tokens, Some("Bearer CH4"), Some("x"), &action)
+        );
+        assert_eq!(
+            Ok(Some("reader".to_string())),
+            authorize(
+                &tokens,
+                Some("Bearer CH4"),
+                Some("x"),
+                &get_action("history", None)
+            )
+        );
+
+        assert_eq!(
+            Ok(Some("cron".to_string())),
+            authorize(&tokens, Some("Bearer C3H8"), Some("x"), &action)
+        );
+        assert_eq!(
+            Err(AuthError::Forbidden("cron".to_string())),
+            authorize(
+                &tokens,
+                Some("Bearer C3H8"),
+                Some("x"),
+                &get_action("update", None)
+            )
+        );
+    }
+
+    #[test]
+    pub fn test_authorize_failures() {
+        let tokens = get_tokens();
+
+        assert_eq!(
+            Err(AuthError::MissingToken),
+            authorize(&tokens, None, Some("foo.acme.tld"), "update")
+        );
+        assert_eq!(
+            Err(AuthError::MissingToken),
+            authorize(
+                &tokens,
+                Some("Basic Zm9vOmJhcg=="),
+                Some("foo.acme.tld"),
+                "update"
+            )
+        );
+        assert_eq!(
+            Err(AuthError::InvalidToken),
+            authorize(&tokens, Some("Bearer CH4"), Some("foo.acme.tld"), "update")
+        );
+        assert_eq!(
+            Err(AuthError::Forbidden("jenkins".to_string())),
+            authorize(
+                &tokens,
+                Some("Bearer CH3-CH3"),
+                Some("foo.acme.tld"),
+                "init"
+            )
+        );
+        assert_eq!(
+            Err(AuthError::Forbidden("jenkins".to_string())),
+            authorize(
+                &tokens,
+                Some("Bearer CH3-CH3"),
+                Some("foo.example.org"),
+                "update"
+            )
+        );
+    }
+}
@@ -0,0 +1,338 @@
+//  -------------------------------------------------------------
+//  Alkane :: Server :: Forges
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+//  Project:        Nasqueron
+//  License:        BSD-2-Clause
+//  Description:    Push webhooks sent by Git forges
+//  -------------------------------------------------------------
+
+use axum::http::HeaderMap;
+use serde::Deserialize;
+
+use crate::runner::site::Revision;
+use crate::server::signature::{verify_hub_signature, SignatureError};
+
+//  -------------------------------------------------------------
+//  Forges
+//
+//  GitHub, Gitea and Forgejo share the same push payload format,
+//  and sign it with X-Hub-Signature-256.
+//
+//  GitLab sends a token as is in X-Gitlab-Token. It's the site gitlab_token,
+//  never the webhook secret, as it's sent in plain text.
+//
+//  Each forge identifies a delivery by an UUID, remembered with the
+//  signature in the database, so a captured signed webhook can't be
+//  replayed. GitLab deliveries aren't signed: the UUID only prevents
+//  redeliveries, as a captured token can be reused with a new UUID.
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+const GITLAB_TOKEN_HEADER: &str = "X-Gitlab-Token";
+
+/// A commit SHA made of zeros, sent when a branch is deleted
+const NULL_COMMIT: &str = "0000000000000000000000000000000000000000";
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Forge {
+    GitHub,
+    Gitea,
+    Forgejo,
+    GitLab,
+}
+
+impl Forge {
+    fn get_event_header(&self) -> &'static str {
+        match self {
+            Forge::GitHub => "X-GitHub-Event",
+            Forge::Gitea => "X-Gitea-Event",
+            Forge::Forgejo => "X-Forgejo-Event",
+            Forge::GitLab => "X-Gitlab-Event",
+        }
+    }
+
+    fn get_delivery_header(&self) -> &'static str {
+        match self {
+            Forge::GitHub => "X-GitHub-Delivery",
+            Forge::Gitea => "X-Gitea-Delivery",
+            Forge::Forgejo => "X-Forgejo-Delivery",
+            Forge::GitLab => "X-Gitlab-Event-UUID",
+        }
+    }
+
+    fn get_push_event_name(&self) -> &'static str {
+        match self {
+            Forge::GitLab => "Push Hook",
+            _ => "push",
+        }
+    }
+}
+
+//  -------------------------------------------------------------
+//  Push events
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+#[derive(Debug, PartialEq)]
+pub struct PushEvent {
+    pub revision: Revision,
+
+    /// The default branch of the repository, if sent by the forge
+    pub default_branch: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct HubPushPayload {
+    #[serde(rename = "ref")]
+    reference: String,
+    after: String,
+    repository: HubRepository,
+}
+
+#[derive(Deserialize)]
+struct HubRepository {
+    full_name: String,
+    default_branch: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GitLabPushPayload {
+    #[serde(rename = "ref")]
+    reference: String,
+    checkout_sha: Option<String>,
+    project: GitLabProject,
+}
+
+#[derive(Deserialize)]
+struct GitLabProject {
+    path_with_namespace: String,
+    default_branch: Option<String>,
+}
+
+/// Parses a webhook payload sent by a forge.
+///
+/// Returns None for events not leading to a deployment: other events
+/// than push like ping, tags pushes and branches deletions.
+pub fn parse_push_event(
+    forge: Forge,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> Result<Option<PushEvent>, serde_json::Error> {
+    let event = headers
+        .get(forge.get_event_header())
+        .and_then(|value| value.to_str().ok());
+
+    if event != Some(forge.get_push_event_name()) {
+        return Ok(None);
+    }
+
+    let (reference, commit, repository, default_branch) = match forge {
+        Forge::GitLab => {
+            let payload: GitLabPushPayload = serde_json::from_slice(body)?;
+
+            (
+                payload.reference,
+                payload.checkout_sha.unwrap_or_default(),
+                payload.project.path_with_namespace,
+                payload.project.default_branch,
+            )
+        }
+
+        _ => {
+            let payload: HubPushPayload = serde_json::from_slice(body)?;
+
+            (
+                payload.reference,
+                payload.after,
+                payload.repository.full_name,
+                payload.repository.default_branch,
+            )
+        }
+    };
+
+    let branch = match reference.strip_prefix("refs/heads/") {
+        None => return Ok(None),
+        Some(branch) => branch.to_string(),
+    };
+
+    if commit.is_empty() || commit == NULL_COMMIT {
+        return Ok(None);
+    }
+
+    Ok(Some(PushEvent {
+        revision: Revision {
+            repository,
+            branch,
+            commit,
+        },
+        default_branch,
+    }))
+}
+
+//  -------------------------------------------------------------
+//  Authentication
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+/// Verifies the webhook has been sent with the site secret,
+/// the webhook secret to sign the body or the GitLab token.
+///
+/// Returns the keys identifying the delivery to remember against replay:
+/// the delivery ID, and the signature if the forge signs requests.
+pub fn verify_webhook(
+    forge: Forge,
+    secret: &str,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> Result<Vec<String>, SignatureError> {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+
+    let delivery = header(forge.get_delivery_header())
+        .map(|delivery| delivery.trim())
+        .filter(|delivery| !delivery.is_empty())
+        .map(|delivery| format!("{}:{}", forge.get_delivery_header(), delivery));
+
+    match forge {
+        Forge::GitLab => {
+            let token = header(GITLAB_TOKEN_HEADER).ok_or(SignatureError::Missing)?;
+
+            if !is_same_secret(token, secret) {
+                return Err(SignatureError::Invalid);
+            }
+
+            // The body isn't signed, so the delivery ID is the only way to detect a redelivery
+            delivery
+                .map(|delivery| vec![delivery])
+                .ok_or(SignatureError::Missing)
+        }
+
+        _ => {
+            let signature = verify_hub_signature(secret, headers, body)?;
+
+            Ok(delivery.into_iter().chain([signature]).collect())
+        }
+    }
+}
+
+/// Compares secrets in constant time, so the comparison duration
+/// doesn't leak how many characters are correct.
+fn is_same_secret(candidate: &str, secret: &str) -> bool {
+    candidate.len() == secret.len()
+        && candidate
+            .bytes()
+            .zip(secret.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+//  -------------------------------------------------------------
+//  Tests
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GITHUB_PUSH: &str = r#"{
+        "ref": "refs/heads/main",
+        "before": "1111111111111111111111111111111111111111",
+        "after": "2222222222222222222222222222222222222222",
+        "repository": {
+            "full_name": "acme/foo",
+            "default_branch": "main"
+        }
+    }"#;
+
+    const GITLAB_PUSH: &str = r#"{
+        "object_kind": "push",
+        "ref": "refs/heads/develop",
+        "checkout_sha": "3333333333333333333333333333333333333333",
+        "project": {
+            "path_with_namespace": "acme/foo",
+            "default_branch": "main"
+        }
+    }"#;
+
+    fn get_headers(name: &'static str, value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, value.parse().unwrap());
+
+        headers
+    }
+
+    #[test]
+    pub fn test_parse_github_push() {
+        let headers = get_headers("X-GitHub-Event", "push");
+        let event = parse_push_event(Forge::GitHub, &headers, GITHUB_PUSH.as_bytes());
+
+        let expected = PushEvent {
+            revision: Revision {
+                repository: "acme/foo".to_string(),
+                branch: "main".to_string(),
+                commit: "2222222222222222222222222222222222222222".to_string(),
+            },
+            default_branch: Some("main".to_string()),
+        };
+        assert_eq!(Some(expected), event.unwrap());
+    }
+
+    #[test]
+    pub fn test_parse_gitlab_push() {
+        let headers = get_headers("X-Gitlab-Event", "Push Hook");
+        let event = parse_push_event(Forge::GitLab, &headers, GITLAB_PUSH.as_bytes())
+            .unwrap()
+            .unwrap();
+
+        assert_eq!("develop", event.revision.branch);
+        assert_eq!(
+            "3333333333333333333333333333333333333333",
+            event.revision.commit
+        );
+    }
+
+    #[test]
+    pub fn test_parse_ignored_events() {
+        // Other events than push
+        let headers = get_headers("X-GitHub-Event", "ping");
+        let event = parse_push_event(Forge::GitHub, &headers, b"{}");
+        assert_eq!(None, event.unwrap());
+
+        // Tags
+        let headers = get_headers("X-Gitea-Event", "push");
+        let payload = GITHUB_PUSH.replace("refs/heads/main", "refs/tags/1.0.0");
+        let event = parse_push_event(Forge::Gitea, &headers, payload.as_bytes());
+        assert_eq!(None, event.unwrap());
+
+        // Branch deletion
+        let payload = GITHUB_PUSH.replace("2222222222222222222222222222222222222222", NULL_COMMIT);
+        let event = parse_push_event(Forge::Gitea, &headers, payload.as_bytes());
+        assert_eq!(None, event.unwrap());
+
+        // Malformed payload
+        let event = parse_push_event(Forge::Gitea, &headers, b"CH3-CH3");
+        assert!(event.is_err());
+    }
+
+    #[test]
+    pub fn test_verify_gitlab_token() {
+        let mut headers = get_headers(GITLAB_TOKEN_HEADER, "t0ken");
+
+        // Without delivery ID, a redelivery couldn't be detected
+        assert_eq!(
+            Err(SignatureError::Missing),
+            verify_webhook(Forge::GitLab, "t0ken", &headers, b"")
+        );
+
+        headers.insert("X-Gitlab-Event-UUID", "1234".parse().unwrap());
+        assert_eq!(
+            Ok(vec!["X-Gitlab-Event-UUID:1234".to_string()]),
+            verify_webhook(Forge::GitLab, "t0ken", &headers, b"")
+        );
+        assert_eq!(
+            Err(SignatureError::Invalid),
+            verify_webhook(Forge::GitLab, "other", &headers, b"")
+        );
+        assert_eq!(
+            Err(SignatureError::Missing),
+            verify_webhook(Forge::GitLab, "t0ken", &HeaderMap::new(), b"")
+        );
+    }
+}
@@ -0,0 +1,275 @@
+//  -------------------------------------------------------------
+//  Alkane :: Server :: Jobs
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+//  Project:        Nasqueron
+//  License:        BSD-2-Clause
+//  Description:    Deployments run in background for the HTTP API
+//  -------------------------------------------------------------
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use log::warn;
+use serde::Serialize;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use crate::deploy::{AlkaneDeployError, DeployError, DeploymentResult};
+
+/// The number of finished jobs to keep in memory, so they can be polled
+const FINISHED_JOBS_RETENTION: usize = 100;
+
+//  -------------------------------------------------------------
+//  Job
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+#[derive(Clone, Copy, Debug, Serialize, PartialEq)]
+pub enum JobState {
+    /// The job is waiting for a worker
+    Queued,
+
+    /// The recipe is running
+    Running,
+
+    /// The recipe ran, the result is available
+    Completed,
+
+    /// The deployment couldn't run, the error is available
+    Failed,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Job {
+    pub id: String,
+
+    /// The name of the site to deploy
+    pub site_name: String,
+
+    /// The deployment action requested, "init", "update" or "deploy"
+    pub action: String,
+
+    pub state: JobState,
+
+    pub created_at: DateTime<Utc>,
+
+    pub finished_at: Option<DateTime<Utc>>,
+
+    /// The result of the deployment, when completed
+    pub result: Option<DeploymentResult>,
+
+    /// The error preventing the deployment to run, when failed
+    pub error: Option<String>,
+
+    /// The machine-readable code of the error, like "lock_held"
+    pub error_code: Option<String>,
+}
+
+impl Job {
+    fn new(site_name: &str, action: &str) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            site_name: site_name.to_string(),
+            action: action.to_string(),
+            state: JobState::Queued,
+            created_at: Utc::now(),
+            finished_at: None,
+            result: None,
+            error: None,
+            error_code: None,
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        self.finished_at.is_some()
+    }
+}
+
+//  -------------------------------------------------------------
+//  Jobs registry
+//
+//  Jobs are kept in memory: they don't survive a server restart,
+//  the deployments journal remains the source of truth.
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+#[derive(Clone, Default)]
+pub struct JobsRegistry {
+    jobs: Arc<Mutex<HashMap<String, Job>>>,
+}
+
+impl JobsRegistry {
+    /// Registers a new job and returns its identifier
+    pub fn create(&self, site_name: &str, action: &str) -> String {
+        let job = Job::new(site_name, action);
+        let id = job.id.clone();
+
+        let mut jobs = self.jobs.lock().unwrap();
+        prune_finished_jobs(&mut jobs);
+        jobs.insert(id.clone(), job);
+
+        id
+    }
+
+    pub fn set_running(&self, id: &str) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(id) {
+            job.state = JobState::Running;
+        }
+    }
+
+    pub fn set_finished(&self, id: &str, result: Result<DeploymentResult, DeployError>) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(id) {
+            job.finished_at = Some(Utc::now());
+
+            match result {
+                Ok(result) => {
+                    job.state = JobState::Completed;
+                    job.result = Some(result);
+                }
+
+                Err(error) => {
+                    job.state = JobState::Failed;
+                    job.result = error.get_result().cloned();
+                    job.error = Some(error.to_string());
+                    job.error_code = Some(error.get_code().to_string());
+                }
+            }
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<Job> {
+        self.jobs.lock().unwrap().get(id).cloned()
+    }
+
+    /// Runs the deployment of a job outside the async runtime.
+    /// If the deployment panics, the job fails with an internal error,
+    /// instead of staying running forever.
+    pub fn spawn<F>(&self, id: &str, deployment: F) -> JoinHandle<()>
+    where
+        F: FnOnce() -> Result<DeploymentResult, DeployError> + Send + 'static,
+    {
+        let jobs = self.clone();
+        let id = id.to_string();
+
+        let task_jobs = self.clone();
+        let task_id = id.clone();
+        let task = tokio::task::spawn_blocking(move || {
+            task_jobs.set_running(&task_id);
+            deployment()
+        });
+
+        tokio::spawn(async move {
+            let result = match task.await {
+                Ok(result) => result,
+                Err(error) => {
+                    warn!("Job {} task failed: {}", &id, error);
+
+                    let (site_name, action) = jobs
+                        .get(&id)
+                        .map(|job| (job.site_name, job.action))
+                        .unwrap_or_default();
+                    let message = "Deployment task failed";
+                    let error = AlkaneDeployError::new(message, &site_name, &action);
+                    Err(DeployError::Internal(error))
+                }
+            };
+
+            jobs.set_finished(&id, result);
+        })
+    }
+}
+
+fn prune_finished_jobs(jobs: &mut HashMap<String, Job>) {
+    let mut finished: Vec<(String, DateTime<Utc>)> = jobs
+        .values()
+        .filter(|job| job.is_finished())
+        .map(|job| (job.id.clone(), job.created_at))
+        .collect();
+
+    if finished.len() < FINISHED_JOBS_RETENTION {
+        return;
+    }
+
+    finished.sort_by_key(|(_, created_at)| *created_at);
+    let excess = finished.len() - FINISHED_JOBS_RETENTION + 1;
+    for (id, _) in finished.into_iter().take(excess) {
+        jobs.remove(&id);
+    }
+}
+
+//  -------------------------------------------------------------
+//  Tests
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::RecipeStatus;
+
+    #[test]
+    pub fn test_job_lifecycle() {
+        let registry = JobsRegistry::default();
+        let id = registry.create("foo.acme.tld", "deploy");
+
+        assert_eq!(JobState::Queued, registry.get(&id).unwrap().state);
+
+        registry.set_running(&id);
+        assert_eq!(JobState::Running, registry.get(&id).unwrap().state);
+
+        let mut result =
+            DeploymentResult::new("foo.acme.tld", "update", RecipeStatus::Success, Utc::now());
+        result.run_id = Some(1);
+        registry.set_finished(&id, Ok(result));
+        let job = registry.get(&id).unwrap();
+        assert_eq!(JobState::Completed, job.state);
+        assert!(job.is_finished());
+
+        assert!(registry.get("notexisting").is_none());
+    }
+
+    #[test]
+    pub fn test_failed_job() {
+        let registry = JobsRegistry::default();
+        let id = registry.create("foo.acme.tld", "update");
+
+        let error = AlkaneDeployError::new("No recipe", "foo.acme.tld", "update");
+        registry.set_finished(&id, Err(DeployError::RecipeNotFound(error)));
+
+        let job = registry.get(&id).unwrap();
+        assert_eq!(JobState::Failed, job.state);
+        assert_eq!(Some("recipe_not_found".to_string()), job.error_code);
+    }
+
+    #[tokio::test]
+    pub async fn test_panicked_job() {
+        let registry = JobsRegistry::default();
+        let id = registry.create("foo.acme.tld", "update");
+
+        registry
+            .spawn(&id, || panic!("Recipe runner crashed"))
+            .await
+            .unwrap();
+
+        let job = registry.get(&id).unwrap();
+        assert_eq!(JobState::Failed, job.state);
+        assert_eq!(Some("internal".to_string()), job.error_code);
+        assert!(job.is_finished());
+    }
+
+    #[test]
+    pub fn test_prune_finished_jobs() {
+        let registry = JobsRegistry::default();
+
+        for _ in 0..FINISHED_JOBS_RETENTION + 5 {
+            let id = registry.create("foo.acme.tld", "update");
+            let result =
+                DeploymentResult::new("foo.acme.tld", "update", RecipeStatus::Success, Utc::now());
+            registry.set_finished(&id, Ok(result));
+        }
+
+        let unfinished = registry.create("foo.acme.tld", "update");
+
+        let jobs = registry.jobs.lock().unwrap();
+        assert!(jobs.len() <= FINISHED_JOBS_RETENTION + 1);
+        assert!(jobs.contains_key(&unfinished));
+    }
+}
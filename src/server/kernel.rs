@@ -6,11 +6,16 @@
 //  -------------------------------------------------------------
 
 use axum::Router;
+use axum::middleware;
 use axum::routing::{get, post};
 use limiting_factor_axum::app::{App, ServerConfig};
 
 use crate::config::AlkaneConfig;
+use crate::server::auth::authenticate;
+use crate::server::metrics::track_requests;
 use crate::server::requests::*;
+use crate::server::signature::verify_signature;
+use crate::server::state::ServerState;
 
 //  -------------------------------------------------------------
 //  Server entry point
@@ -24,24 +29,47 @@ pub fn get_default_config () -> ServerConfig {
     }
 }
 
-pub fn get_router () -> Router<AlkaneConfig> {
+pub fn get_router (state: ServerState) -> Router {
+    // Deployments can be requested by webhooks, signed if the site has a secret
+    let deployments = Router::new()
+        .route("/init/{site_name}", post(init))
+        .route("/update/{site_name}", post(update))
+        .route("/deploy/{site_name}", post(deploy))
+        .route("/rollback/{site_name}", post(rollback))
+        .route("/remove/{site_name}", post(remove))
+        .route("/run/{site_name}/{action}", post(run_action))
+        .route_layer(middleware::from_fn_with_state(state.clone(), verify_signature));
+
+    let api = Router::new()
+        .merge(deployments)
+        .route("/sites", get(sites))
+        .route("/is_present/{site_name}", get(is_present))
+        .route("/history/{site_name}", get(history))
+        .route("/logs/{site_name}/{run_id}", get(logs))
+        .route("/jobs/{job_id}", get(job))
+        .route("/health", get(health))
+        .route("/metrics", get(metrics))
+        .route_layer(middleware::from_fn_with_state(state.clone(), authenticate));
+
     Router::new()
 
         // Monitoring
         .route("/status", get(status))
 
-        // Alkane API
-        .route("/init/{site_name}", post(init))
-        .route("/update/{site_name}", post(update))
-        .route("/deploy/{site_name}", post(deploy))
-        .route("/is_present/{site_name}", get(is_present))
+        // Alkane API, requires authentication if tokens are configured
+        .merge(api)
+
+        // Git forges webhooks, authenticated by the sites webhook secrets
+        .route("/webhooks/{forge}", post(forge_webhook))
+
+        .route_layer(middleware::from_fn_with_state(state.clone(), track_requests))
+        .with_state(state)
 }
 
 pub async fn run(alkane_config: AlkaneConfig) -> bool {
     let server_config = ServerConfig::from_env_or(get_default_config());
 
-    let router = get_router()
-        .with_state(alkane_config);
+    let router = get_router(ServerState::new(alkane_config));
 
     App::new(server_config, router).run().await
 }
@@ -0,0 +1,545 @@
+//  -------------------------------------------------------------
+//  Alkane :: Server :: Metrics
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+//  Project:        Nasqueron
+//  License:        BSD-2-Clause
+//  Description:    Deployments and requests metrics for Prometheus
+//  -------------------------------------------------------------
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use axum::extract::{MatchedPath, Request, State};
+use axum::middleware::Next;
+use axum::response::Response;
+
+use crate::config::AlkaneConfig;
+use crate::db::Database;
+use crate::deploy::{DeployError, DeploymentResult};
+use crate::runner::RecipeStatus;
+use crate::services::site_name::is_valid_site_name;
+
+/// The upper bounds of the deployment duration histogram, in seconds
+const DURATION_BUCKETS: [f64; 10] = [
+    1.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0, 3600.0,
+];
+
+/// The site label of errors for sites Alkane doesn't know, so requests
+/// for arbitrary site names don't create new time series
+const UNKNOWN_SITE: &str = "_unknown";
+
+//  -------------------------------------------------------------
+//  Metrics registry
+//
+//  Counters are kept in memory since the server started, and only
+//  cover the deployments requested through the HTTP API. The last
+//  successful deployment of each site is read from the journals
+//  at startup, so it also covers the previous ones.
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+#[derive(Clone, Default)]
+pub struct Metrics {
+    data: Arc<Mutex<MetricsData>>,
+}
+
+#[derive(Default)]
+struct MetricsData {
+    /// Deployments by site, action and recipe status
+    deployments: BTreeMap<(String, String, String), u64>,
+
+    /// Deployments duration by site and action
+    durations: BTreeMap<(String, String), Histogram>,
+
+    /// Deployments errors by site and error code
+    errors: BTreeMap<(String, String), u64>,
+
+    /// Deployments refused as another one held the site lock, by site
+    lock_conflicts: BTreeMap<String, u64>,
+
+    /// Deployments currently running
+    in_flight: u64,
+
+    /// Unix timestamp of the last successful deployment, by site
+    last_success: BTreeMap<String, i64>,
+
+    /// CPU time used by the recipes, in milliseconds, by site and action
+    cpu_time: BTreeMap<(String, String), u64>,
+
+    /// Maximum resident set size of the last recipe run, in KiB, by site
+    max_rss: BTreeMap<String, u64>,
+
+    /// HTTP requests by route and status code
+    requests: BTreeMap<(String, u16), u64>,
+}
+
+#[derive(Default)]
+struct Histogram {
+    /// The observations count for each bucket of DURATION_BUCKETS
+    buckets: [u64; DURATION_BUCKETS.len()],
+
+    count: u64,
+
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        for (bucket, bound) in self.buckets.iter_mut().zip(DURATION_BUCKETS) {
+            if value <= bound {
+                *bucket += 1;
+            }
+        }
+
+        self.count += 1;
+        self.sum += value;
+    }
+}
+
+impl Metrics {
+    /// Creates the metrics registry, with the last successful
+    /// deployment of each site from the journals
+    pub fn load(config: &AlkaneConfig) -> Self {
+        let metrics = Self::default();
+
+        if let Some(db) = Database::from_config(config) {
+            let mut data = metrics.data.lock().unwrap();
+
+            for site_name in db.get_initialized_sites() {
+                let last_success = db
+                    .find_last_history_entry(&site_name, |entry| is_successful(&entry.status))
+                    .map(|entry| entry.timestamp.timestamp() + (entry.duration / 1000) as i64);
+
+                if let Some(timestamp) = last_success {
+                    data.last_success.insert(site_name, timestamp);
+                }
+            }
+        }
+
+        metrics
+    }
+
+    /// Runs a deployment action, counting it as in flight while it runs,
+    /// then records its outcome.
+    pub fn measure_deployment<F>(
+        &self,
+        site_name: &str,
+        config: &AlkaneConfig,
+        run: F,
+    ) -> Result<DeploymentResult, DeployError>
+    where
+        F: FnOnce() -> Result<DeploymentResult, DeployError>,
+    {
+        let in_flight = InFlightGuard::new(&self.data);
+        let outcome = run();
+        drop(in_flight);
+
+        match &outcome {
+            Ok(result) => self.data.lock().unwrap().record_result(result),
+            Err(error) => {
+                let site = get_site_label(site_name, config);
+                self.data.lock().unwrap().record_error(site, error);
+            }
+        }
+
+        outcome
+    }
+
+    pub fn record_request(&self, route: &str, status: u16) {
+        let mut data = self.data.lock().unwrap();
+
+        *data
+            .requests
+            .entry((route.to_string(), status))
+            .or_default() += 1;
+    }
+
+    /// Renders the metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let data = self.data.lock().unwrap();
+        let mut output = String::new();
+
+        write_header(
+            &mut output,
+            "alkane_deployments_total",
+            "counter",
+            "Deployments by site, action and recipe status",
+        );
+        for ((site, action, status), count) in &data.deployments {
+            let labels = format_labels(&[("site", site), ("action", action), ("status", status)]);
+            writeln!(output, "alkane_deployments_total{} {}", labels, count).unwrap();
+        }
+
+        write_header(
+            &mut output,
+            "alkane_deployment_duration_seconds",
+            "histogram",
+            "Deployments duration by site and action",
+        );
+        for ((site, action), histogram) in &data.durations {
+            let name = "alkane_deployment_duration_seconds";
+            for (bound, count) in DURATION_BUCKETS.iter().zip(histogram.buckets) {
+                let bound = bound.to_string();
+                let labels = format_labels(&[("site", site), ("action", action), ("le", &bound)]);
+                writeln!(output, "{}_bucket{} {}", name, labels, count).unwrap();
+            }
+
+            let labels = format_labels(&[("site", site), ("action", action), ("le", "+Inf")]);
+            writeln!(output, "{}_bucket{} {}", name, labels, histogram.count).unwrap();
+
+            let labels = format_labels(&[("site", site), ("action", action)]);
+            writeln!(output, "{}_sum{} {}", name, labels, histogram.sum).unwrap();
+            writeln!(output, "{}_count{} {}", name, labels, histogram.count).unwrap();
+        }
+
+        write_header(
+            &mut output,
+            "alkane_deployment_errors_total",
+            "counter",
+            "Deployments errors by site and error code",
+        );
+        for ((site, error), count) in &data.errors {
+            let labels = format_labels(&[("site", site), ("error", error)]);
+            writeln!(output, "alkane_deployment_errors_total{} {}", labels, count).unwrap();
+        }
+
+        write_header(
+            &mut output,
+            "alkane_lock_conflicts_total",
+            "counter",
+            "Deployments refused as another deployment held the site lock",
+        );
+        for (site, count) in &data.lock_conflicts {
+            let labels = format_labels(&[("site", site)]);
+            writeln!(output, "alkane_lock_conflicts_total{} {}", labels, count).unwrap();
+        }
+
+        write_header(
+            &mut output,
+            "alkane_deployments_in_flight",
+            "gauge",
+            "Deployments currently running",
+        );
+        writeln!(output, "alkane_deployments_in_flight {}", data.in_flight).unwrap();
+
+        write_header(
+            &mut output,
+            "alkane_last_success_timestamp_seconds",
+            "gauge",
+            "Unix timestamp of the last successful deployment of the site",
+        );
+        for (site, timestamp) in &data.last_success {
+            let labels = format_labels(&[("site", site)]);
+            let name = "alkane_last_success_timestamp_seconds";
+            writeln!(output, "{}{} {}", name, labels, timestamp).unwrap();
+        }
+
+        write_header(
+            &mut output,
+            "alkane_recipe_cpu_seconds_total",
+            "counter",
+            "CPU time used by the recipes, in user and system modes",
+        );
+        for ((site, action), milliseconds) in &data.cpu_time {
+            let labels = format_labels(&[("site", site), ("action", action)]);
+            let seconds = *milliseconds as f64 / 1000.0;
+            writeln!(
+                output,
+                "alkane_recipe_cpu_seconds_total{} {}",
+                labels, seconds
+            )
+            .unwrap();
+        }
+
+        write_header(
+            &mut output,
+            "alkane_recipe_max_rss_bytes",
+            "gauge",
+            "Maximum resident set size of the last recipe run of the site",
+        );
+        for (site, kibibytes) in &data.max_rss {
+            let labels = format_labels(&[("site", site)]);
+            let bytes = kibibytes * 1024;
+            writeln!(output, "alkane_recipe_max_rss_bytes{} {}", labels, bytes).unwrap();
+        }
+
+        write_header(
+            &mut output,
+            "alkane_http_requests_total",
+            "counter",
+            "HTTP requests by route and status code",
+        );
+        for ((route, status), count) in &data.requests {
+            let status = status.to_string();
+            let labels = format_labels(&[("route", route), ("status", &status)]);
+            writeln!(output, "alkane_http_requests_total{} {}", labels, count).unwrap();
+        }
+
+        output
+    }
+}
+
+/// Counts a deployment as in flight until dropped,
+/// so it's not counted forever if the deployment panics
+struct InFlightGuard<'a> {
+    data: &'a Mutex<MetricsData>,
+}
+
+impl<'a> InFlightGuard<'a> {
+    fn new(data: &'a Mutex<MetricsData>) -> Self {
+        data.lock().unwrap().in_flight += 1;
+
+        Self { data }
+    }
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut data) = self.data.lock() {
+            data.in_flight -= 1;
+        }
+    }
+}
+
+impl MetricsData {
+    fn record_result(&mut self, result: &DeploymentResult) {
+        let site = result.site_name.clone();
+        let action = result.action.clone();
+        let status = format!("{:?}", result.status);
+
+        *self
+            .deployments
+            .entry((site.clone(), action.clone(), status))
+            .or_default() += 1;
+
+        self.durations
+            .entry((site.clone(), action.clone()))
+            .or_default()
+            .observe(result.duration as f64 / 1000.0);
+
+        if is_successful(&result.status) {
+            self.last_success
+                .insert(site.clone(), result.finished_at.timestamp());
+        }
+
+        if let Some(usage) = &result.usage {
+            *self.cpu_time.entry((site.clone(), action)).or_default() += usage.get_cpu_time();
+            self.max_rss.insert(site, usage.max_rss);
+        }
+    }
+
+    /// Records a deployment error, for the site label given by get_site_label
+    fn record_error(&mut self, site_name: &str, error: &DeployError) {
+        match error {
+            DeployError::Execution(error) => self.record_result(&error.result),
+            DeployError::Timeout(_, result) => self.record_result(result),
+
+            DeployError::LockHeld(_) => {
+                *self
+                    .lock_conflicts
+                    .entry(site_name.to_string())
+                    .or_default() += 1;
+            }
+
+            _ => {}
+        }
+
+        let key = (site_name.to_string(), error.get_code().to_string());
+        *self.errors.entry(key).or_default() += 1;
+    }
+}
+
+fn is_successful(status: &RecipeStatus) -> bool {
+    matches!(status, RecipeStatus::Success | RecipeStatus::Warning)
+}
+
+/// Gets the site label for a deployment error: the site name if the site
+/// has a section in the configuration, a recipes directory or has been
+/// initialized, UNKNOWN_SITE otherwise.
+fn get_site_label<'a>(site_name: &'a str, config: &AlkaneConfig) -> &'a str {
+    if !is_valid_site_name(site_name) {
+        return UNKNOWN_SITE;
+    }
+
+    let is_known = config.get_sites_config().contains_key(site_name)
+        || config
+            .get_root("recipes")
+            .is_some_and(|root| Path::new(&root).join(site_name).is_dir())
+        || Database::from_config(config).is_some_and(|db| db.is_initialized(site_name));
+
+    if is_known {
+        site_name
+    } else {
+        UNKNOWN_SITE
+    }
+}
+
+//  -------------------------------------------------------------
+//  Text exposition format helpers
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+fn write_header(output: &mut String, name: &str, metric_type: &str, help: &str) {
+    writeln!(output, "# HELP {} {}", name, help).unwrap();
+    writeln!(output, "# TYPE {} {}", name, metric_type).unwrap();
+}
+
+fn format_labels(labels: &[(&str, &str)]) -> String {
+    let labels: Vec<String> = labels
+        .iter()
+        .map(|(name, value)| format!("{}=\"{}\"", name, escape_label_value(value)))
+        .collect();
+
+    format!("{{{}}}", labels.join(","))
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+//  -------------------------------------------------------------
+//  Middleware
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+/// Counts the HTTP requests by route and status code.
+///
+/// Set as a route layer, so requests to unknown paths aren't counted.
+pub async fn track_requests(
+    State(metrics): State<Metrics>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_default();
+
+    let response = next.run(request).await;
+    metrics.record_request(&route, response.status().as_u16());
+
+    response
+}
+
+//  -------------------------------------------------------------
+//  Tests
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+    use crate::db::lock::LockInfo;
+    use crate::deploy::{AlkaneDeployError, LockHeldError};
+    use crate::runner::ResourceUsage;
+
+    #[test]
+    pub fn test_escape_label_value() {
+        assert_eq!("foo.acme.tld", escape_label_value("foo.acme.tld"));
+        assert_eq!(r#"a\"b\\c\nd"#, escape_label_value("a\"b\\c\nd"));
+    }
+
+    #[test]
+    pub fn test_histogram() {
+        let mut histogram = Histogram::default();
+        histogram.observe(0.5);
+        histogram.observe(45.0);
+
+        assert_eq!(2, histogram.count);
+        assert_eq!(45.5, histogram.sum);
+        assert_eq!([1, 1, 1, 1, 2, 2, 2, 2, 2, 2], histogram.buckets);
+    }
+
+    #[test]
+    pub fn test_render() {
+        let metrics = Metrics::default();
+        let config = AlkaneConfig::load().unwrap();
+
+        let mut result =
+            DeploymentResult::new("foo.acme.tld", "update", RecipeStatus::Success, Utc::now());
+        result.usage = Some(ResourceUsage {
+            wall_time: 1500,
+            user_time: 1000,
+            system_time: 500,
+            max_rss: 2048,
+        });
+        metrics
+            .measure_deployment("foo.acme.tld", &config, || Ok(result))
+            .unwrap();
+
+        let error = DeployError::InvalidRequest(AlkaneDeployError::new(
+            "Invalid action name",
+            "foo.acme.tld",
+            "../update",
+        ));
+        assert!(metrics
+            .measure_deployment("foo.acme.tld", &config, || Err(error))
+            .is_err());
+
+        let error = DeployError::SiteUnresolvable(AlkaneDeployError::new(
+            "Can't resolve site path",
+            "random.example.org",
+            "update",
+        ));
+        assert!(metrics
+            .measure_deployment("random.example.org", &config, || Err(error))
+            .is_err());
+
+        let error = DeployError::LockHeld(LockHeldError {
+            site_name: "foo.acme.tld".to_string(),
+            action: "update".to_string(),
+            holder: Some(LockInfo {
+                pid: 1,
+                started_at: Utc::now(),
+                action: "update".to_string(),
+            }),
+        });
+        assert!(metrics
+            .measure_deployment("foo.acme.tld", &config, || Err(error))
+            .is_err());
+
+        metrics.record_request("/update/{site_name}", 200);
+
+        let output = metrics.render();
+        let expected_lines = [
+            r#"alkane_deployments_total{site="foo.acme.tld",action="update",status="Success"} 1"#,
+            r#"alkane_deployment_duration_seconds_count{site="foo.acme.tld",action="update"} 1"#,
+            r#"alkane_deployment_errors_total{site="foo.acme.tld",error="invalid_request"} 1"#,
+            r#"alkane_deployment_errors_total{site="foo.acme.tld",error="lock_held"} 1"#,
+            r#"alkane_deployment_errors_total{site="_unknown",error="site_unresolvable"} 1"#,
+            r#"alkane_lock_conflicts_total{site="foo.acme.tld"} 1"#,
+            "alkane_deployments_in_flight 0",
+            r#"alkane_recipe_cpu_seconds_total{site="foo.acme.tld",action="update"} 1.5"#,
+            r#"alkane_recipe_max_rss_bytes{site="foo.acme.tld"} 2097152"#,
+            r#"alkane_http_requests_total{route="/update/{site_name}",status="200"} 1"#,
+        ];
+        for line in expected_lines {
+            assert!(
+                output.lines().any(|candidate| candidate == line),
+                "{}",
+                line
+            );
+        }
+
+        assert!(output.contains("# TYPE alkane_deployment_duration_seconds histogram\n"));
+        assert!(output.contains("alkane_last_success_timestamp_seconds{site=\"foo.acme.tld\"}"));
+        assert!(!output.contains("random.example.org"));
+    }
+
+    #[test]
+    pub fn test_in_flight_after_panic() {
+        let metrics = Metrics::default();
+        let config = AlkaneConfig::load().unwrap();
+
+        let outcome = std::panic::catch_unwind(|| {
+            metrics.measure_deployment("foo.acme.tld", &config, || panic!("Recipe runner bug"))
+        });
+        assert!(outcome.is_err());
+
+        assert_eq!(0, metrics.data.lock().unwrap().in_flight);
+    }
+}
@@ -9,5 +9,11 @@
 //  Modules
 //  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
 
+pub mod auth;
+pub mod forges;
+pub mod jobs;
 pub mod kernel;
+pub mod metrics;
 pub mod requests;
+pub mod signature;
+pub mod state;
@@ -5,17 +5,112 @@
 //  License:        BSD-2-Clause
 //  -------------------------------------------------------------
 
-use axum::extract::{Path, State};
-use axum::http::StatusCode;
+use std::convert::Infallible;
+use std::io::{Error as IOError, ErrorKind};
+
+use axum::body::Bytes;
+use axum::extract::{Path, Query, State};
+use axum::http::header::CONTENT_TYPE;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+
+use futures_util::stream;
 
 use limiting_factor_axum::api::guards::AxumRequestBody as RequestBody;
-use limiting_factor_axum::api::replies::{ApiJsonResponse, ApiResponse, FailureResponse};
+use limiting_factor_axum::api::replies::{ApiJsonResponse, ApiResponse};
 
 use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
 use crate::actions;
 use crate::config::AlkaneConfig;
-use crate::deploy::DeployError;
-use crate::runner::RecipeStatus;
+use crate::db::history::{HistoryEntry, Trigger};
+use crate::db::logs::RunLog;
+use crate::db::Database;
+use crate::deploy::{DeployError, DeploymentResult};
+use crate::health::check_health;
+use crate::inventory;
+use crate::inventory::SiteInfo;
+use crate::removal::RemoveOptions;
+use crate::runner::{OutputLine, OutputListener};
+use crate::server::forges::{parse_push_event, verify_webhook, Forge};
+use crate::server::jobs::{Job, JobsRegistry};
+use crate::server::metrics::Metrics;
+use crate::server::signature::SignatureError;
+use crate::server::state::ServerState;
+use crate::services::site_name::is_valid_site_name;
+
+/// The result of a deployment action from the actions module
+type DeploymentOutcome = Result<DeploymentResult, DeployError>;
+
+#[derive(Debug, Deserialize)]
+pub struct DeploymentParameters {
+    /// If true, run the deployment in background and reply with a job ID
+    #[serde(default, rename = "async")]
+    pub is_async: bool,
+
+    /// If true, reply with what would be done, without running anything
+    #[serde(default)]
+    pub dry_run: bool,
+
+    /// If true, stream the recipe output as Server-Sent Events while it runs
+    #[serde(default)]
+    pub stream: bool,
+
+    /// If set, add to the result this number of lines from the end of the recipe output
+    pub output_tail: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RollbackParameters {
+    /// The release to roll back to, by default the previous one
+    pub to: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JobAccepted {
+    pub job_id: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WebhookJob {
+    pub site_name: String,
+    pub job_id: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorReply {
+    /// A stable machine-readable error code, like "lock_held"
+    pub error: &'static str,
+
+    pub message: String,
+
+    /// The result of the run, if the recipe has been started
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<DeploymentResult>,
+}
+
+impl ErrorReply {
+    pub fn new(error: &'static str, message: &str) -> Self {
+        Self {
+            error,
+            message: message.to_string(),
+            result: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct JobReport {
+    #[serde(flatten)]
+    pub job: Job,
+
+    /// The recipe output, when the job is completed
+    pub output: Option<RunLog>,
+}
 
 //  -------------------------------------------------------------
 //  Monitoring
@@ -25,6 +120,31 @@ pub async fn status() -> &'static str {
     "ALIVE"
 }
 
+pub async fn health(State(config): State<AlkaneConfig>) -> Response {
+    // Checking the filesystem blocks the thread
+    let report = match tokio::task::spawn_blocking(move || check_health(Ok(&config))).await {
+        Ok(report) => report,
+        Err(error) => {
+            warn!("Health check task failed: {}", error);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let status = if report.healthy {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (status, Json(report)).into_response()
+}
+
+pub async fn metrics(State(metrics): State<Metrics>) -> impl IntoResponse {
+    let content_type = "text/plain; version=0.0.4; charset=utf-8";
+
+    ([(CONTENT_TYPE, content_type)], metrics.render())
+}
+
 //  -------------------------------------------------------------
 //  Alkane requests
 //  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
@@ -33,64 +153,490 @@ pub async fn is_present(
     Path(site_name): Path<String>,
     State(config): State<AlkaneConfig>,
 ) -> ApiJsonResponse<bool> {
+    check_site_name(&site_name)?;
+
     actions::is_present(&site_name, &config).into_json_response()
 }
 
-pub async fn init(
+pub async fn sites(
+    State(config): State<AlkaneConfig>,
+) -> ApiJsonResponse<Vec<SiteInfo>> {
+    inventory::list_sites(&config).into_json_response()
+}
+
+pub async fn history(
     Path(site_name): Path<String>,
     State(config): State<AlkaneConfig>,
-    context: RequestBody,
-) -> ApiJsonResponse<RecipeStatus> {
-    info!("Deploying {}", &site_name);
+) -> ApiJsonResponse<Vec<HistoryEntry>> {
+    check_site_name(&site_name)?;
 
-    let context = context.into_optional_string();
-    debug!("Context: {:?}", &context);
+    actions::get_history(&site_name, &config).into_json_response()
+}
+
+pub async fn logs(
+    Path((site_name, run_id)): Path<(String, u64)>,
+    State(config): State<AlkaneConfig>,
+) -> ApiJsonResponse<RunLog> {
+    check_site_name(&site_name)?;
+
+    match actions::get_run_log(&site_name, Some(run_id), &config) {
+        Some(log) => log.into_json_response(),
+        None => Err((StatusCode::NOT_FOUND, Json("No log available for this run".to_string()))),
+    }
+}
+
+/// Rejects a site name which can't safely be used as a path component,
+/// like `..`, before it reaches the filesystem.
+fn check_site_name(site_name: &str) -> Result<(), (StatusCode, Json<String>)> {
+    if is_valid_site_name(site_name) {
+        Ok(())
+    } else {
+        Err((
+            StatusCode::BAD_REQUEST,
+            Json("Invalid site name".to_string()),
+        ))
+    }
+}
 
-    actions::initialize(&site_name, context, &config)
-        .into_json_response()
+pub async fn init(
+    Path(site_name): Path<String>,
+    Query(parameters): Query<DeploymentParameters>,
+    State(state): State<ServerState>,
+    context: RequestBody,
+) -> Response {
+    run_deployment(site_name, "init", parameters, state, context, actions::initialize).await
 }
 
 pub async fn update(
     Path(site_name): Path<String>,
-    State(config): State<AlkaneConfig>,
+    Query(parameters): Query<DeploymentParameters>,
+    State(state): State<ServerState>,
     context: RequestBody,
-) -> ApiJsonResponse<RecipeStatus> {
-    info!("Deploying {}", &site_name);
+) -> Response {
+    run_deployment(site_name, "update", parameters, state, context, actions::update).await
+}
 
-    let context = context.into_optional_string();
-    debug!("Context: {:?}", &context);
+pub async fn deploy(
+    Path(site_name): Path<String>,
+    Query(parameters): Query<DeploymentParameters>,
+    State(state): State<ServerState>,
+    context: RequestBody,
+) -> Response {
+    run_deployment(site_name, "deploy", parameters, state, context, actions::deploy).await
+}
+
+pub async fn run_action(
+    Path((site_name, action)): Path<(String, String)>,
+    Query(parameters): Query<DeploymentParameters>,
+    State(state): State<ServerState>,
+    context: RequestBody,
+) -> Response {
+    let action_name = action.clone();
+
+    let run = move |site_name: &str, context, trigger, listener, config: &AlkaneConfig| {
+        actions::run_custom_action(site_name, &action, context, trigger, listener, config)
+    };
 
-    actions::update(&site_name, context, &config)
-        .into_json_response()
+    run_deployment(site_name, &action_name, parameters, state, context, run).await
 }
 
-pub async fn deploy(
+pub async fn remove(
     Path(site_name): Path<String>,
-    State(config): State<AlkaneConfig>,
+    Query(options): Query<RemoveOptions>,
+    State(state): State<ServerState>,
+) -> Response {
+    info!("Removing {} ({:?})", &site_name, &options);
+
+    let result = tokio::task::spawn_blocking(move || {
+        state
+            .metrics
+            .measure_deployment(&site_name, &state.config, || {
+                actions::remove(&site_name, options, Trigger::Http, &state.config)
+            })
+    })
+    .await;
+
+    match result {
+        Ok(result) => result.map(Json).into_response(),
+        Err(error) => {
+            warn!("Removal task failed: {}", error);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub async fn rollback(
+    Path(site_name): Path<String>,
+    Query(parameters): Query<RollbackParameters>,
+    State(state): State<ServerState>,
+) -> Response {
+    info!("Rolling back {}", &site_name);
+
+    // Waiting for the site lock and switching the release block the thread
+    let result = tokio::task::spawn_blocking(move || {
+        state
+            .metrics
+            .measure_deployment(&site_name, &state.config, || {
+                actions::rollback(&site_name, parameters.to, Trigger::Http, &state.config)
+            })
+    })
+    .await;
+
+    match result {
+        Ok(result) => result.map(Json).into_response(),
+        Err(error) => {
+            warn!("Rollback task failed: {}", error);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Runs a deployment action from the actions module
+async fn run_deployment<F>(
+    site_name: String,
+    action_name: &str,
+    parameters: DeploymentParameters,
+    state: ServerState,
     context: RequestBody,
-) -> ApiJsonResponse<RecipeStatus> {
-    info!("Deploying {}", &site_name);
+    action: F,
+) -> Response
+where
+    F: FnOnce(
+        &str,
+        Option<String>,
+        Trigger,
+        Option<OutputListener>,
+        &AlkaneConfig,
+    ) -> DeploymentOutcome,
+    F: Send + 'static,
+{
+    info!("Deploying {} ({})", &site_name, action_name);
+
+    // Checked before replying 202 Accepted to an asynchronous deployment
+    if let Err(error) = check_site_name(&site_name) {
+        return error.into_response();
+    }
 
     let context = context.into_optional_string();
     debug!("Context: {:?}", &context);
 
-    actions::deploy(&site_name, context, &config)
-        .into_json_response()
+    if parameters.dry_run {
+        return actions::plan(&site_name, context, action_name, &state.config)
+            .map(Json)
+            .into_response();
+    }
+
+    if parameters.stream {
+        return stream_deployment(site_name, context, state, action);
+    }
+
+    if !parameters.is_async {
+        // Recipes are run outside the async runtime, so a long recipe
+        // doesn't prevent other requests, like for other sites, to be served.
+        let output_tail = parameters.output_tail;
+        let result = tokio::task::spawn_blocking(move || {
+            state
+                .metrics
+                .measure_deployment(&site_name, &state.config, || {
+                    action(&site_name, context, Trigger::Http, None, &state.config)
+                })
+                .map(|result| with_output_tail(result, output_tail, &state.config))
+        })
+        .await;
+
+        return match result {
+            Ok(result) => result.map(Json).into_response(),
+            Err(error) => {
+                warn!("Deployment task failed: {}", error);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        };
+    }
+
+    let job_id = state.jobs.create(&site_name, action_name);
+    info!("Job {} created to deploy {}", &job_id, &site_name);
+
+    let jobs = state.jobs.clone();
+    jobs.spawn(&job_id, move || {
+        state
+            .metrics
+            .measure_deployment(&site_name, &state.config, || {
+                action(&site_name, context, Trigger::Http, None, &state.config)
+            })
+    });
+
+    (StatusCode::ACCEPTED, Json(JobAccepted { job_id })).into_response()
+}
+
+/// Runs a deployment action, sending each line of the recipe output
+/// as a stdout or stderr event, then the outcome as a result or error event.
+fn stream_deployment<F>(
+    site_name: String,
+    context: Option<String>,
+    state: ServerState,
+    action: F,
+) -> Response
+where
+    F: FnOnce(
+        &str,
+        Option<String>,
+        Trigger,
+        Option<OutputListener>,
+        &AlkaneConfig,
+    ) -> DeploymentOutcome,
+    F: Send + 'static,
+{
+    let (sender, receiver) = mpsc::unbounded_channel();
+
+    // If the client disconnects, the events are dropped,
+    // but the recipe still runs to completion.
+    let lines = sender.clone();
+    let listener = OutputListener::new(move |line: OutputLine| {
+        let event = Event::default()
+            .event(line.channel.to_string())
+            .data(line.line);
+        let _ = lines.send(event);
+    });
+
+    tokio::task::spawn_blocking(move || {
+        let result = state
+            .metrics
+            .measure_deployment(&site_name, &state.config, || {
+                action(
+                    &site_name,
+                    context,
+                    Trigger::Http,
+                    Some(listener),
+                    &state.config,
+                )
+            });
+        let event = match result {
+            Ok(result) => Event::default().event("result").json_data(result),
+            Err(error) => {
+                warn!("{}", error); // Server log
+                Event::default()
+                    .event("error")
+                    .json_data(ErrorReply::from(error))
+            }
+        };
+
+        match event {
+            Ok(event) => {
+                let _ = sender.send(event);
+            }
+            Err(error) => warn!("Can't serialize deployment outcome: {}", error),
+        }
+    });
+
+    // The stream ends when the deployment task drops its senders
+    let events = stream::unfold(receiver, |mut receiver| async move {
+        let event = receiver.recv().await?;
+        Some((Ok::<Event, Infallible>(event), receiver))
+    });
+
+    Sse::new(events)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+/// Adds the last lines of the recipe output to the result, if requested
+fn with_output_tail(
+    mut result: DeploymentResult,
+    lines: Option<usize>,
+    config: &AlkaneConfig,
+) -> DeploymentResult {
+    if let (Some(lines), Some(run_id)) = (lines, result.run_id) {
+        result.output = actions::get_run_log(&result.site_name, Some(run_id), config)
+            .map(|log| log.tail(lines));
+    }
+
+    result
+}
+
+pub async fn job(
+    Path(job_id): Path<String>,
+    State(jobs): State<JobsRegistry>,
+    State(config): State<AlkaneConfig>,
+) -> ApiJsonResponse<JobReport> {
+    let job = jobs
+        .get(&job_id)
+        .ok_or_else(|| (StatusCode::NOT_FOUND, Json("Job not found".to_string())))?;
+
+    let output = job
+        .result
+        .as_ref()
+        .and_then(|result| result.run_id)
+        .and_then(|run_id| actions::get_run_log(&job.site_name, Some(run_id), &config));
+
+    JobReport { job, output }.into_json_response()
+}
+
+//  -------------------------------------------------------------
+//  Git forges webhooks
+//
+//  A push deploys every site configured for the repository and branch.
+//  Only sites with a webhook secret can be deployed this way,
+//  as forges can't send bearer tokens.
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+pub async fn forge_webhook(
+    Path(forge): Path<Forge>,
+    State(state): State<ServerState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let event = match parse_push_event(forge, &headers, &body) {
+        Ok(Some(event)) => event,
+        Ok(None) => return Json(Vec::<WebhookJob>::new()).into_response(),
+        Err(error) => {
+            warn!("Can't parse {:?} webhook payload: {}", forge, error);
+            let reply = ErrorReply::new("invalid_request", "Can't parse push event payload");
+            return (StatusCode::BAD_REQUEST, Json(reply)).into_response();
+        }
+    };
+
+    let revision = event.revision;
+    let site_names = state.config.get_sites_for_push(
+        &revision.repository,
+        &revision.branch,
+        event.default_branch.as_deref(),
+    );
+    info!(
+        "Push to {} {} ({}), sites to deploy: {:?}",
+        &revision.repository, &revision.branch, &revision.commit, &site_names
+    );
+
+    let mut authorized_sites = Vec::new();
+    let mut deliveries = Vec::new();
+    let mut last_error = None;
+
+    for site_name in site_names {
+        // GitLab sends its token in plain text, so it can't be the HMAC secret
+        let secret = match forge {
+            Forge::GitLab => state.config.get_gitlab_token(&site_name),
+            _ => state.config.get_webhook_secret(&site_name),
+        };
+
+        let secret = match secret {
+            Some(secret) => secret,
+            None => {
+                warn!(
+                    "Site {} can't be deployed by {:?} webhook without secret",
+                    &site_name, forge
+                );
+                continue;
+            }
+        };
+
+        match verify_webhook(forge, secret, &headers, &body) {
+            Ok(keys) => {
+                deliveries.extend(keys);
+                authorized_sites.push(site_name);
+            }
+            Err(error) => {
+                warn!("Webhook verification failed for {}: {:?}", &site_name, &error);
+                last_error = Some(error);
+            }
+        }
+    }
+
+    if authorized_sites.is_empty() {
+        if let Some(error) = last_error {
+            return error.into_response();
+        }
+
+        return (StatusCode::ACCEPTED, Json(Vec::<WebhookJob>::new())).into_response();
+    }
+
+    deliveries.sort();
+    deliveries.dedup();
+    let cache = state.signatures.clone();
+    let config = state.config.clone();
+    let remembered = tokio::task::spawn_blocking(move || {
+        let db = Database::from_config(&config)
+            .ok_or_else(|| IOError::new(ErrorKind::NotFound, "No db root in configuration"))?;
+
+        cache.remember_delivery(&db, &deliveries)
+    })
+    .await;
+
+    match remembered {
+        Ok(Ok(true)) => {}
+        Ok(Ok(false)) => {
+            warn!("Webhook for {} already received", &revision.commit);
+            return SignatureError::Replayed.into_response();
+        }
+        Ok(Err(error)) => {
+            warn!("Can't record webhook delivery: {:?}", error);
+            let reply = ErrorReply::new("internal", "Can't record webhook delivery");
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(reply)).into_response();
+        }
+        Err(error) => {
+            warn!("Webhook delivery task failed: {}", error);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+
+    let jobs: Vec<WebhookJob> = authorized_sites
+        .into_iter()
+        .map(|site_name| {
+            let job_id = state.jobs.create(&site_name, "deploy");
+            info!("Job {} created to deploy {}", &job_id, &site_name);
+
+            let jobs = state.jobs.clone();
+            let metrics = state.metrics.clone();
+            let config = state.config.clone();
+            let revision = revision.clone();
+            let name = site_name.clone();
+            jobs.spawn(&job_id, move || {
+                metrics.measure_deployment(&name, &config, || {
+                    actions::deploy_revision(&name, None, revision, Trigger::Http, None, &config)
+                })
+            });
+
+            WebhookJob { site_name, job_id }
+        })
+        .collect();
+
+    (StatusCode::ACCEPTED, Json(jobs)).into_response()
 }
 
 //  -------------------------------------------------------------
 //  Custom error handling
 //
-//  Deploy errors are returned as 400 + the Alkane error message
+//  Deploy errors are returned as a JSON object with a stable error
+//  code and the Alkane error message, with a status code according
+//  to the error, like 409 if another deployment is running.
 //  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
 
-impl FailureResponse for DeployError {
-    fn status_code(&self) -> StatusCode {
-        StatusCode::BAD_REQUEST
+impl From<DeployError> for ErrorReply {
+    fn from(error: DeployError) -> Self {
+        ErrorReply {
+            error: error.get_code(),
+            message: error.to_string(),
+            result: error.get_result().cloned(),
+        }
     }
+}
+
+impl IntoResponse for DeployError {
+    fn into_response(self) -> Response {
+        warn!("{}", self); // Server log
+
+        let status_code = match &self {
+            DeployError::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+            DeployError::ActionDisabled(_) => StatusCode::FORBIDDEN,
+            DeployError::SiteUnresolvable(_) => StatusCode::NOT_FOUND,
+            DeployError::RecipeNotFound(_) => StatusCode::NOT_FOUND,
+            DeployError::LockHeld(_) => StatusCode::CONFLICT,
+            DeployError::Timeout(..) => StatusCode::GATEWAY_TIMEOUT,
+            DeployError::ConfigMissing(_)
+            | DeployError::UserUnresolvable(_)
+            | DeployError::RecipeNotExecutable(_)
+            | DeployError::Execution(_)
+            | DeployError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
 
-    fn response(&self) -> String {
-        warn!("{}", self);     // Server log
-        format!("{}", self)    // API response
+        (status_code, Json(ErrorReply::from(self))).into_response()
     }
 }
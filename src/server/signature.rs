@@ -0,0 +1,463 @@
+//  -------------------------------------------------------------
+//  Alkane :: Server :: Signature
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+//  Project:        Nasqueron
+//  License:        BSD-2-Clause
+//  Description:    Verify HMAC-SHA256 signed webhook requests
+//  -------------------------------------------------------------
+
+use std::collections::HashMap;
+use std::io::Error as IOError;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::body::{to_bytes, Body};
+use axum::extract::{OriginalUri, RawPathParams, Request, State};
+use axum::http::{HeaderMap, Method, StatusCode, Uri};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::{Json, RequestExt};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use log::warn;
+use sha2::Sha256;
+
+use crate::db::Database;
+use crate::server::requests::ErrorReply;
+use crate::server::state::ServerState;
+
+//  -------------------------------------------------------------
+//  Headers
+//
+//  Deployment requests are signed with X-Alkane-Signature, covering
+//  "<timestamp>.<METHOD> <path?query>.<body>", with the UNIX timestamp
+//  used sent in X-Alkane-Timestamp. As the request target is signed,
+//  a signed update can't be replayed as a remove or a custom action.
+//
+//  GitHub, Gitea and Forgejo sign only the body with X-Hub-Signature-256,
+//  without timestamp, so such signatures are only accepted for forges
+//  webhooks, which parse the body to know what to deploy.
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+const HUB_SIGNATURE_HEADER: &str = "X-Hub-Signature-256";
+const ALKANE_SIGNATURE_HEADER: &str = "X-Alkane-Signature";
+const ALKANE_TIMESTAMP_HEADER: &str = "X-Alkane-Timestamp";
+
+/// The maximal body size to read to verify the signature
+const BODY_LIMIT: usize = 1_000_000;
+
+type HmacSha256 = Hmac<Sha256>;
+
+//  -------------------------------------------------------------
+//  Signature errors
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+#[derive(Debug, PartialEq)]
+pub enum SignatureError {
+    /// No signature header has been sent
+    Missing,
+
+    /// The signature doesn't match the body
+    Invalid,
+
+    /// The timestamp is missing, malformed or outside the tolerance window
+    Expired,
+
+    /// The same signed request has already been received
+    Replayed,
+
+    /// The body can't be read
+    UnreadableBody,
+}
+
+impl IntoResponse for SignatureError {
+    fn into_response(self) -> Response {
+        let message = match self {
+            SignatureError::Missing => "Signature required",
+            SignatureError::Invalid => "Invalid signature",
+            SignatureError::Expired => "Signature timestamp missing or outside tolerance window",
+            SignatureError::Replayed => "Signed request already received",
+            SignatureError::UnreadableBody => "Can't read request body",
+        };
+
+        let (status, code) = match self {
+            SignatureError::UnreadableBody => (StatusCode::BAD_REQUEST, "invalid_request"),
+            _ => (StatusCode::UNAUTHORIZED, "unauthorized"),
+        };
+
+        (status, Json(ErrorReply::new(code, message))).into_response()
+    }
+}
+
+//  -------------------------------------------------------------
+//  Signature verification
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+/// Verifies the deployment request is signed with the secret,
+/// returns the signature to remember it against replay.
+pub fn verify(
+    secret: &str,
+    method: &Method,
+    uri: &Uri,
+    headers: &HeaderMap,
+    body: &[u8],
+    tolerance: Duration,
+) -> Result<String, SignatureError> {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+
+    let signature = header(ALKANE_SIGNATURE_HEADER).ok_or(SignatureError::Missing)?;
+
+    let timestamp = header(ALKANE_TIMESTAMP_HEADER)
+        .and_then(|timestamp| timestamp.trim().parse::<i64>().ok())
+        .ok_or(SignatureError::Expired)?;
+
+    let age = Utc::now().timestamp().abs_diff(timestamp);
+    if age > tolerance.as_secs() {
+        return Err(SignatureError::Expired);
+    }
+
+    let target = uri
+        .path_and_query()
+        .map(|target| target.as_str())
+        .unwrap_or_else(|| uri.path());
+    let mut payload = format!("{}.{} {}.", timestamp, method, target).into_bytes();
+    payload.extend_from_slice(body);
+
+    verify_hmac(secret, signature, &payload)?;
+    Ok(signature.to_string())
+}
+
+/// Verifies the body is signed with the secret in X-Hub-Signature-256,
+/// as sent by GitHub, Gitea and Forgejo webhooks.
+/// Returns the signature to remember it against replay.
+pub fn verify_hub_signature(
+    secret: &str,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> Result<String, SignatureError> {
+    let signature = headers
+        .get(HUB_SIGNATURE_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .ok_or(SignatureError::Missing)?;
+
+    verify_hmac(secret, signature, body)?;
+    Ok(signature.to_string())
+}
+
+fn verify_hmac(secret: &str, signature: &str, payload: &[u8]) -> Result<(), SignatureError> {
+    let signature = signature.trim();
+    let signature = signature.strip_prefix("sha256=").unwrap_or(signature);
+    let signature = hex::decode(signature).map_err(|_| SignatureError::Invalid)?;
+
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).map_err(|_| SignatureError::Invalid)?;
+    mac.update(payload);
+
+    mac.verify_slice(&signature)
+        .map_err(|_| SignatureError::Invalid)
+}
+
+//  -------------------------------------------------------------
+//  Replay protection
+//
+//  Signatures are remembered twice the tolerance window, as a timestamp
+//  can be up to the tolerance in the future. After that, deployment
+//  requests signatures are rejected as expired.
+//
+//  Forges webhooks have no timestamp, so their deliveries are kept
+//  in the database instead, to be remembered across restarts.
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+#[derive(Clone, Default)]
+pub struct SignaturesCache {
+    seen: Arc<Mutex<HashMap<String, Instant>>>,
+
+    /// Serializes the updates of the deliveries journal
+    deliveries: Arc<Mutex<()>>,
+}
+
+impl SignaturesCache {
+    /// Remembers the signature, returns false if it has already been seen
+    pub fn remember(&self, signature: &str, tolerance: Duration) -> bool {
+        let mut seen = self.seen.lock().unwrap();
+        seen.retain(|_, received_at| received_at.elapsed() <= tolerance * 2);
+
+        seen.insert(signature.to_string(), Instant::now()).is_none()
+    }
+
+    /// Remembers the keys of a delivery in the database,
+    /// returns false if the delivery has already been received
+    pub fn remember_delivery(&self, db: &Database, keys: &[String]) -> Result<bool, IOError> {
+        let _guard = self.deliveries.lock().unwrap();
+
+        db.remember_delivery(keys)
+    }
+}
+
+//  -------------------------------------------------------------
+//  Middleware
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+pub async fn verify_signature(
+    State(state): State<ServerState>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, SignatureError> {
+    let site_name = request
+        .extract_parts::<RawPathParams>()
+        .await
+        .ok()
+        .and_then(|parameters| {
+            parameters
+                .iter()
+                .find(|(name, _)| *name == "site_name")
+                .map(|(_, value)| value.to_string())
+        });
+
+    let secret = site_name
+        .as_deref()
+        .and_then(|site_name| state.config.get_webhook_secret(site_name));
+
+    let secret = match secret {
+        None => return Ok(next.run(request).await),
+        Some(secret) => secret.to_string(),
+    };
+
+    let (parts, body) = request.into_parts();
+    let body = to_bytes(body, BODY_LIMIT)
+        .await
+        .map_err(|_| SignatureError::UnreadableBody)?;
+
+    // The client signs the URI it requested, before the mount point is stripped
+    let uri = parts
+        .extensions
+        .get::<OriginalUri>()
+        .map(|uri| uri.0.clone())
+        .unwrap_or_else(|| parts.uri.clone());
+
+    let tolerance = state.config.get_webhook_tolerance();
+    let result = verify(
+        &secret,
+        &parts.method,
+        &uri,
+        &parts.headers,
+        &body,
+        tolerance,
+    )
+    .and_then(|signature| {
+        if state.signatures.remember(&signature, tolerance) {
+            Ok(())
+        } else {
+            Err(SignatureError::Replayed)
+        }
+    });
+
+    if let Err(error) = result {
+        warn!(
+            "Signature verification failed for site {:?}: {:?}",
+            &site_name, &error
+        );
+
+        return Err(error);
+    }
+
+    let request = Request::from_parts(parts, Body::from(body));
+    Ok(next.run(request).await)
+}
+
+//  -------------------------------------------------------------
+//  Tests
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOLERANCE: Duration = Duration::from_secs(300);
+
+    fn sign(secret: &str, payload: &[u8]) -> String {
+        let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(payload);
+
+        format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+    }
+
+    fn get_alkane_headers(timestamp: i64, payload: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            ALKANE_TIMESTAMP_HEADER,
+            timestamp.to_string().parse().unwrap(),
+        );
+        headers.insert(
+            ALKANE_SIGNATURE_HEADER,
+            sign("s3cret", payload.as_bytes()).parse().unwrap(),
+        );
+
+        headers
+    }
+
+    #[test]
+    pub fn test_verify_hub_signature() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            HUB_SIGNATURE_HEADER,
+            sign("s3cret", b"CH3-CH3").parse().unwrap(),
+        );
+
+        assert!(verify_hub_signature("s3cret", &headers, b"CH3-CH3").is_ok());
+        assert_eq!(
+            Err(SignatureError::Invalid),
+            verify_hub_signature("s3cret", &headers, b"CH4")
+        );
+        assert_eq!(
+            Err(SignatureError::Invalid),
+            verify_hub_signature("other", &headers, b"CH3-CH3")
+        );
+    }
+
+    #[test]
+    pub fn test_verify_hub_signature_deployment() {
+        // The body signature doesn't cover the target, so it could be
+        // replayed to another route: deployments require X-Alkane-Signature.
+        let uri: Uri = "/remove/foo.acme.tld?confirm=true".parse().unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            HUB_SIGNATURE_HEADER,
+            sign("s3cret", b"CH3-CH3").parse().unwrap(),
+        );
+        assert_eq!(
+            Err(SignatureError::Missing),
+            verify(
+                "s3cret",
+                &Method::POST,
+                &uri,
+                &headers,
+                b"CH3-CH3",
+                TOLERANCE
+            )
+        );
+
+        let now = Utc::now().timestamp();
+        headers.insert(ALKANE_TIMESTAMP_HEADER, now.to_string().parse().unwrap());
+        assert_eq!(
+            Err(SignatureError::Missing),
+            verify(
+                "s3cret",
+                &Method::POST,
+                &uri,
+                &headers,
+                b"CH3-CH3",
+                TOLERANCE
+            )
+        );
+    }
+
+    #[test]
+    pub fn test_verify_alkane_signature() {
+        let uri: Uri = "/update/foo.acme.tld".parse().unwrap();
+
+        let now = Utc::now().timestamp();
+        let payload = format!("{}.POST /update/foo.acme.tld.CH3-CH3", now);
+        let headers = get_alkane_headers(now, &payload);
+        assert!(verify(
+            "s3cret",
+            &Method::POST,
+            &uri,
+            &headers,
+            b"CH3-CH3",
+            TOLERANCE
+        )
+        .is_ok());
+
+        let old = now - 3600;
+        let payload = format!("{}.POST /update/foo.acme.tld.CH3-CH3", old);
+        let headers = get_alkane_headers(old, &payload);
+        assert_eq!(
+            Err(SignatureError::Expired),
+            verify(
+                "s3cret",
+                &Method::POST,
+                &uri,
+                &headers,
+                b"CH3-CH3",
+                TOLERANCE
+            )
+        );
+    }
+
+    #[test]
+    pub fn test_verify_alkane_signature_target() {
+        let now = Utc::now().timestamp();
+        let payload = format!("{}.POST /update/foo.acme.tld.", now);
+        let headers = get_alkane_headers(now, &payload);
+
+        for target in [
+            "/remove/foo.acme.tld?confirm=true",
+            "/run/foo.acme.tld/clear-cache",
+            "/update/foo.acme.tld?async=true",
+        ] {
+            let uri: Uri = target.parse().unwrap();
+            assert_eq!(
+                Err(SignatureError::Invalid),
+                verify("s3cret", &Method::POST, &uri, &headers, b"", TOLERANCE)
+            );
+        }
+
+        let uri: Uri = "/update/foo.acme.tld".parse().unwrap();
+        assert_eq!(
+            Err(SignatureError::Invalid),
+            verify("s3cret", &Method::GET, &uri, &headers, b"", TOLERANCE)
+        );
+    }
+
+    #[test]
+    pub fn test_verify_unsigned() {
+        let uri: Uri = "/update/foo.acme.tld".parse().unwrap();
+
+        assert_eq!(
+            Err(SignatureError::Missing),
+            verify(
+                "s3cret",
+                &Method::POST,
+                &uri,
+                &HeaderMap::new(),
+                b"CH3-CH3",
+                TOLERANCE
+            )
+        );
+    }
+
+    #[test]
+    pub fn test_replay() {
+        let cache = SignaturesCache::default();
+
+        assert!(cache.remember("sha256=abcd", TOLERANCE));
+        assert!(!cache.remember("sha256=abcd", TOLERANCE));
+        assert!(cache.remember("sha256=ef01", TOLERANCE));
+    }
+
+    #[test]
+    pub fn test_replay_delivery_after_window() {
+        let root = std::env::temp_dir().join(format!("alkane-test-replay-{}", std::process::id()));
+        let db = Database::new(root.to_str().unwrap());
+        let cache = SignaturesCache::default();
+
+        let keys = vec![
+            "X-GitHub-Delivery:1234".to_string(),
+            "sha256=abcd".to_string(),
+        ];
+        assert!(cache.remember("sha256=abcd", Duration::ZERO));
+        assert!(cache.remember_delivery(&db, &keys).unwrap());
+
+        // Once the window has passed, the signature is forgotten by the cache,
+        // but the delivery is still known, even by a restarted server.
+        std::thread::sleep(Duration::from_millis(10));
+        assert!(cache.remember("sha256=abcd", Duration::ZERO));
+
+        let cache = SignaturesCache::default();
+        assert!(!cache.remember_delivery(&db, &keys).unwrap());
+
+        std::fs::remove_dir_all(root).expect("Can't remove temporary database.")
+    }
+}
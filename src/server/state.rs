@@ -0,0 +1,53 @@
+//  -------------------------------------------------------------
+//  Alkane :: Server :: State
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+//  Project:        Nasqueron
+//  License:        BSD-2-Clause
+//  Description:    Shared state available to the requests
+//  -------------------------------------------------------------
+
+use axum::extract::FromRef;
+
+use crate::config::AlkaneConfig;
+use crate::server::jobs::JobsRegistry;
+use crate::server::metrics::Metrics;
+use crate::server::signature::SignaturesCache;
+
+#[derive(Clone)]
+pub struct ServerState {
+    pub config: AlkaneConfig,
+    pub jobs: JobsRegistry,
+    pub metrics: Metrics,
+
+    /// Signatures of the webhook requests recently received
+    pub signatures: SignaturesCache,
+}
+
+impl ServerState {
+    pub fn new(config: AlkaneConfig) -> Self {
+        Self {
+            metrics: Metrics::load(&config),
+            config,
+            jobs: JobsRegistry::default(),
+            signatures: SignaturesCache::default(),
+        }
+    }
+}
+
+impl FromRef<ServerState> for AlkaneConfig {
+    fn from_ref(state: &ServerState) -> Self {
+        state.config.clone()
+    }
+}
+
+impl FromRef<ServerState> for JobsRegistry {
+    fn from_ref(state: &ServerState) -> Self {
+        state.jobs.clone()
+    }
+}
+
+impl FromRef<ServerState> for Metrics {
+    fn from_ref(state: &ServerState) -> Self {
+        state.metrics.clone()
+    }
+}
@@ -5,4 +5,5 @@
 //  License:        BSD-2-Clause
 //  -------------------------------------------------------------
 
+pub mod site_name;
 pub mod tld;
@@ -0,0 +1,61 @@
+//  -------------------------------------------------------------
+//  Alkane :: Services :: Site name
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+//  Project:        Nasqueron
+//  License:        BSD-2-Clause
+//  Description:    Validate site names before using them in paths
+//  -------------------------------------------------------------
+
+/// The maximum length of a domain name
+const MAX_SITE_NAME_LENGTH: usize = 253;
+
+/// Determines if a site name is safe to use as a path component,
+/// in the database or to resolve the site directory.
+///
+/// A site name is a dot-separated list of non-empty labels, made of
+/// letters, digits, dashes and underscores, like foo.acme.tld.
+/// A name like `..`, `...acme.tld` or `foo/../bar` is rejected,
+/// so it can't escape the db root or the sites root.
+pub fn is_valid_site_name(site_name: &str) -> bool {
+    !site_name.is_empty()
+        && site_name.len() <= MAX_SITE_NAME_LENGTH
+        && site_name.split('.').all(is_valid_label)
+}
+
+fn is_valid_label(label: &str) -> bool {
+    !label.is_empty()
+        && !label.starts_with('-')
+        && label
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+//  -------------------------------------------------------------
+//  Tests
+//  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_is_valid_site_name() {
+        assert!(is_valid_site_name("foo.acme.tld"));
+        assert!(is_valid_site_name("foo-bar.acme.co.uk"));
+        assert!(is_valid_site_name("_acme-challenge.acme.tld"));
+        assert!(is_valid_site_name("localhost"));
+
+        assert!(!is_valid_site_name(""));
+        assert!(!is_valid_site_name("."));
+        assert!(!is_valid_site_name(".."));
+        assert!(!is_valid_site_name("..acme.tld"));
+        assert!(!is_valid_site_name("...acme.tld"));
+        assert!(!is_valid_site_name("foo.acme.tld."));
+        assert!(!is_valid_site_name("foo/../bar"));
+        assert!(!is_valid_site_name("../etc/passwd"));
+        assert!(!is_valid_site_name("-rf.acme.tld"));
+        assert!(!is_valid_site_name("foo acme.tld"));
+        assert!(!is_valid_site_name("foo\0.acme.tld"));
+        assert!(!is_valid_site_name(&format!("{}ab", "a.".repeat(126))));
+    }
+}
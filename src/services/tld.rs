@@ -27,6 +27,11 @@ lazy_static! {
 //  Helper methods
 //  - - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -
 
+/// Counts the suffixes of the public suffix list
+pub fn count_public_suffixes() -> usize {
+    PUBLIC_SUFFIXES.len()
+}
+
 pub fn get_tld<S>(fqdn: S) -> String
 where
     S: AsRef<str>,